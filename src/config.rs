@@ -1,16 +1,24 @@
 use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::env;
 use std::fs::File;
 use std::io::BufReader;
 use std::rc::Rc;
 
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 
-const CONFIG_PATH: &str = "config.json";
+use super::reporter::{AggregationStrategy, Condition};
+
+const DEFAULT_CONFIG_PATH: &str = "config.json";
+
+// Environment variable to override the config file path.
+const CONFIG_PATH_ENV: &str = "WEATHER_REPORT_CONFIG";
 
 thread_local! {
     // App configuration parsed from JSON file
     pub static CONFIG: Rc<RefCell<Config>> = {
-        let file = File::open(CONFIG_PATH).expect("Couldn't open config file");
+        let path = env::var(CONFIG_PATH_ENV).unwrap_or_else(|_| String::from(DEFAULT_CONFIG_PATH));
+        let file = File::open(path).expect("Couldn't open config file");
         let reader = BufReader::new(file);
         let config: Config = serde_json::from_reader(reader).expect("Couldn't parse config file");
         Rc::new(RefCell::new(config))
@@ -20,23 +28,427 @@ thread_local! {
 #[derive(Clone, Deserialize)]
 pub struct Config {
     pub server: Server,
-    pub providers: Vec<Provider>,
+    pub providers: Vec<ProviderConfig>,
     pub geocoder: Geocoder,
+    #[serde(default)]
+    pub reporter: Reporter,
 }
 
 #[derive(Clone, Deserialize)]
 pub struct Server {
     pub address: String,
+    /// Bearer token required by admin endpoints. They are disabled without it.
+    #[serde(default)]
+    pub admin_token: Option<String>,
+}
+
+/// Provider settings along with the options common to all providers.
+#[derive(Clone, Deserialize)]
+pub struct ProviderConfig {
+    #[serde(flatten)]
+    pub provider: Provider,
+    /// Relative weight of the provider's reports in `weighted_mean` aggregation.
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+    #[serde(default)]
+    pub http: Http,
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreaker,
+    /// Request budget of each of the provider's API keys. Unlimited when not set.
+    #[serde(default)]
+    pub quota: Option<Quota>,
+    #[serde(default)]
+    pub key_rotation: KeyRotation,
+}
+
+/// Rotation of the provider's API keys.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct KeyRotation {
+    pub policy: RotationPolicy,
+    /// Time to skip a key after the upstream has rejected it, ms.
+    pub quarantine_ms: u64,
+}
+
+impl Default for KeyRotation {
+    fn default() -> Self {
+        Self {
+            policy: RotationPolicy::default(),
+            quarantine_ms: 3_600_000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RotationPolicy {
+    /// Keys are taken in turn.
+    #[default]
+    RoundRobin,
+    /// The key with the fewest calls is taken.
+    LeastUsed,
+}
+
+/// Limits of provider calls per window. Windows without a limit aren't restricted.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Quota {
+    pub per_second: Option<u32>,
+    pub per_minute: Option<u32>,
+    pub per_day: Option<u32>,
+}
+
+/// Provider's circuit breaker settings.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct CircuitBreaker {
+    /// Number of consecutive failures to open the breaker.
+    pub failure_threshold: u32,
+    /// Time to skip the provider before letting a probe call through, ms.
+    pub open_ms: u64,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            open_ms: 30_000,
+        }
+    }
 }
 
 #[derive(Clone, Deserialize)]
 #[serde(tag = "name")]
 pub enum Provider {
-    Yandex { api_key: String },
-    OpenWeatherMap { api_key: String },
+    Yandex {
+        #[serde(alias = "api_key", deserialize_with = "one_or_many")]
+        api_keys: Vec<String>,
+        #[serde(default = "default_yandex_base_url")]
+        base_url: String,
+    },
+    OpenWeatherMap {
+        #[serde(alias = "api_key", deserialize_with = "one_or_many")]
+        api_keys: Vec<String>,
+        #[serde(default = "default_open_weather_map_base_url")]
+        base_url: String,
+    },
+    WeatherApi {
+        #[serde(alias = "api_key", deserialize_with = "one_or_many")]
+        api_keys: Vec<String>,
+        #[serde(default = "default_weather_api_base_url")]
+        base_url: String,
+    },
+    /// The key is only needed for the commercial API, which also has its own base URL.
+    OpenMeteo {
+        #[serde(default, alias = "api_key", deserialize_with = "one_or_many")]
+        api_keys: Vec<String>,
+        #[serde(default = "default_open_meteo_base_url")]
+        base_url: String,
+    },
+    /// MET's terms require a `User-Agent` identifying the app and its contact,
+    /// e.g. "weather-report/1.0 ops@example.com".
+    MetNorway {
+        user_agent: String,
+        #[serde(default = "default_met_norway_base_url")]
+        base_url: String,
+    },
+    /// NWS asks for a `User-Agent` identifying the app and its contact as well.
+    Nws {
+        user_agent: String,
+        #[serde(default = "default_nws_base_url")]
+        base_url: String,
+    },
+    /// Upstream described entirely in the config.
+    Generic(Box<Generic>),
+    /// External executable speaking JSON over stdin and stdout, see `provider::Command`.
+    Command {
+        /// Name of the provider in responses.
+        title: String,
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+        /// Time for the process to respond before it's killed, ms.
+        #[serde(default = "default_command_timeout_ms")]
+        timeout_ms: u64,
+    },
+    /// Local CSV or NDJSON file or a directory of such files, see `provider::File`.
+    File {
+        /// Name of the provider in responses.
+        title: String,
+        path: String,
+        /// Largest difference of latitude or longitude between the requested location
+        /// and the one of the records, degrees.
+        #[serde(default = "default_file_max_distance")]
+        max_distance: f32,
+    },
+}
+
+impl Provider {
+    pub fn api_keys(&self) -> &[String] {
+        match self {
+            Provider::Yandex { api_keys, .. }
+            | Provider::OpenWeatherMap { api_keys, .. }
+            | Provider::WeatherApi { api_keys, .. }
+            | Provider::OpenMeteo { api_keys, .. } => api_keys,
+            Provider::Generic(generic) => &generic.api_keys,
+            Provider::MetNorway { .. }
+            | Provider::Nws { .. }
+            | Provider::Command { .. }
+            | Provider::File { .. } => &[],
+        }
+    }
+}
+
+/// Accepts either a single string or a list of strings.
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+/// Declarative HTTP/JSON provider.
+///
+/// `url` and `headers` may contain placeholders `{lat}`, `{lon}`, `{days}` and `{api_key}`.
+/// The response is expected to be JSON with an array of days at the `days` pointer.
+#[derive(Clone, Deserialize)]
+pub struct Generic {
+    /// Name of the provider in responses.
+    pub title: String,
+    pub url: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default, alias = "api_key", deserialize_with = "one_or_many")]
+    pub api_keys: Vec<String>,
+    /// JSON pointer to the array of days, e.g. "/forecast/days". Empty for the root.
+    #[serde(default)]
+    pub days: String,
+    pub mapping: GenericMapping,
+}
+
+/// JSON pointers to values of a day relative to the day's element.
+#[derive(Clone, Deserialize)]
+pub struct GenericMapping {
+    pub date: Field,
+    /// Format of the date string, see `chrono::format::strftime`. Numeric dates are taken
+    /// as Unix timestamps.
+    #[serde(default = "default_date_format")]
+    pub date_format: String,
+    pub temperature: Field,
+    pub temperature_min: Option<Field>,
+    pub temperature_max: Option<Field>,
+    pub humidity: Option<Field>,
+    pub pressure: Option<Field>,
+    pub wind_speed: Option<Field>,
+    pub wind_direction: Option<Field>,
+    pub wind_gust: Option<Field>,
+    pub precipitation: Option<Field>,
+    pub precipitation_probability: Option<Field>,
+    pub cloud_cover: Option<Field>,
+    pub condition: Option<Field>,
+    /// Upstream condition values mapped to condition codes like "light_rain".
+    #[serde(default, deserialize_with = "condition_codes")]
+    pub conditions: BTreeMap<String, Condition>,
+}
+
+/// A JSON pointer with an optional linear conversion: `value * scale + offset`.
+/// Accepts either a pointer string or an object.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(from = "FieldConfig")]
+pub struct Field {
+    pub pointer: String,
+    pub scale: f32,
+    pub offset: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FieldConfig {
+    Pointer(String),
+    Converted {
+        pointer: String,
+        #[serde(default = "default_scale")]
+        scale: f32,
+        #[serde(default)]
+        offset: f32,
+    },
+}
+
+impl From<FieldConfig> for Field {
+    fn from(field: FieldConfig) -> Self {
+        match field {
+            FieldConfig::Pointer(pointer) => Field {
+                pointer,
+                scale: 1.0,
+                offset: 0.0,
+            },
+            FieldConfig::Converted {
+                pointer,
+                scale,
+                offset,
+            } => Field {
+                pointer,
+                scale,
+                offset,
+            },
+        }
+    }
+}
+
+fn condition_codes<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<String, Condition>, D::Error> {
+    BTreeMap::<String, String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(value, code)| match Condition::from_code(&code) {
+            Some(condition) => Ok((value, condition)),
+            None => Err(serde::de::Error::custom(format!(
+                "unknown condition code \"{}\"",
+                code
+            ))),
+        })
+        .collect()
 }
 
 #[derive(Clone, Deserialize)]
 pub struct Geocoder {
     pub api_key: String,
+    #[serde(default = "default_geocoder_base_url")]
+    pub base_url: String,
+    #[serde(default)]
+    pub http: Http,
+}
+
+/// HTTP client settings of an upstream.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct Http {
+    /// Timeout for establishing a connection, ms.
+    pub connect_timeout_ms: u64,
+    /// Timeout for getting the response, ms. Counts from the start of the request.
+    pub read_timeout_ms: u64,
+    /// Number of retries of transient failures after the first attempt.
+    pub max_retries: u32,
+    /// Delay before the first retry, ms. Doubles with each next retry.
+    pub backoff_ms: u64,
+    /// Limit of the delay between retries, ms.
+    pub max_backoff_ms: u64,
+}
+
+impl Default for Http {
+    fn default() -> Self {
+        Self {
+            connect_timeout_ms: 3_000,
+            read_timeout_ms: 10_000,
+            max_retries: 2,
+            backoff_ms: 200,
+            max_backoff_ms: 2_000,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct Reporter {
+    /// Default strategy of combining providers' reports. May be overridden per request.
+    #[serde(default)]
+    pub aggregation: AggregationStrategy,
+    /// Number of providers that have to respond for a forecast to be served.
+    #[serde(default = "default_min_providers")]
+    pub min_providers: usize,
+    /// Time in milliseconds to wait for providers. Providers that don't respond in time
+    /// are reported as timed out.
+    #[serde(default = "default_deadline_ms")]
+    pub deadline_ms: u64,
+    /// Number of threads calling providers. Calls beyond it are skipped as throttled.
+    #[serde(default = "default_workers")]
+    pub workers: usize,
+    /// File to keep providers' quota counters in between restarts.
+    #[serde(default = "default_quota_state_path")]
+    pub quota_state_path: String,
+}
+
+impl Default for Reporter {
+    fn default() -> Self {
+        Self {
+            aggregation: AggregationStrategy::default(),
+            min_providers: default_min_providers(),
+            deadline_ms: default_deadline_ms(),
+            workers: default_workers(),
+            quota_state_path: default_quota_state_path(),
+        }
+    }
+}
+
+fn default_weight() -> f32 {
+    1.0
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+fn default_date_format() -> String {
+    String::from("%Y-%m-%d")
+}
+
+fn default_command_timeout_ms() -> u64 {
+    10_000
+}
+
+fn default_file_max_distance() -> f32 {
+    0.1
+}
+
+fn default_min_providers() -> usize {
+    1
+}
+
+fn default_deadline_ms() -> u64 {
+    10_000
+}
+
+fn default_workers() -> usize {
+    64
+}
+
+fn default_quota_state_path() -> String {
+    String::from("quota_state.json")
+}
+
+// Default upstream base URLs. They may be overridden in the config to go through a proxy,
+// a regional mirror or a local mock server.
+
+fn default_yandex_base_url() -> String {
+    String::from("https://api.weather.yandex.ru")
+}
+
+fn default_open_weather_map_base_url() -> String {
+    String::from("https://api.openweathermap.org")
+}
+
+fn default_weather_api_base_url() -> String {
+    String::from("https://api.weatherapi.com")
+}
+
+fn default_open_meteo_base_url() -> String {
+    String::from("https://api.open-meteo.com")
+}
+
+fn default_met_norway_base_url() -> String {
+    String::from("https://api.met.no")
+}
+
+fn default_nws_base_url() -> String {
+    String::from("https://api.weather.gov")
+}
+
+fn default_geocoder_base_url() -> String {
+    String::from("https://geocode-maps.yandex.ru")
 }
@@ -1,34 +1,40 @@
-use std::cell::RefCell;
 use std::error::Error;
-use std::rc::Rc;
 
+use lazy_static::lazy_static;
 use percent_encoding::{utf8_percent_encode, DEFAULT_ENCODE_SET};
-use reqwest::Client;
 use serde::Deserialize;
 use simple_error::SimpleError;
 
 use super::config::CONFIG;
+use super::http::{self, HttpClient};
 
-thread_local! {
+lazy_static! {
     // Service that is used for searching geo coordinates of locations by string.
-    pub static GEOCODER: Rc<RefCell<Geocoder>> = {
+    // Shared by all workers so that they reuse the client's connections.
+    pub static ref GEOCODER: Geocoder = {
         CONFIG.with(|config| {
-            let geocoder = Geocoder::new(&config.borrow().geocoder.api_key);
-            Rc::new(RefCell::new(geocoder))
+            let geocoder_config = &config.borrow().geocoder;
+            Geocoder::new(
+                &geocoder_config.api_key,
+                &geocoder_config.base_url,
+                HttpClient::new(&geocoder_config.http),
+            )
         })
     };
 }
 
 pub struct Geocoder {
     api_key: String,
-    client: Client,
+    base_url: String,
+    client: HttpClient,
 }
 
 impl Geocoder {
-    pub fn new(api_key: &str) -> Self {
+    pub fn new(api_key: &str, base_url: &str, client: HttpClient) -> Self {
         Self {
             api_key: String::from(api_key),
-            client: Client::new(),
+            base_url: String::from(base_url.trim_end_matches('/')),
+            client,
         }
     }
 
@@ -44,24 +50,27 @@ impl Geocoder {
                 let mut it = feature_member.geo_object.point.pos.split(' ');
                 let lon_parse = it.next().map(|x| x.parse::<f32>()).and_then(|x| x.ok());
                 let lat_parse = it.next().map(|x| x.parse::<f32>()).and_then(|x| x.ok());
-                lon_parse.and_then(|lon| lat_parse.and_then(|lat| Some((lat, lon))))
+                lon_parse.and_then(|lon| lat_parse.map(|lat| (lat, lon)))
             })
             .ok_or_else(|| SimpleError::new("Failed to geocode location").into())
     }
 
     /// Makes a call to Yandex geocoding API and returns the response.
+    /// Errors leave out the request URL since it carries the API key.
     fn get_geocode(&self, location: &str) -> Result<GeocodeResponse, Box<dyn Error>> {
         let escaped_location = utf8_percent_encode(location, DEFAULT_ENCODE_SET).to_string();
 
         let url = format!(
-            "https://geocode-maps.yandex.ru/1.x/?geocode={}&kind=locality&apikey={}&format=json",
-            escaped_location, self.api_key
+            "{}/1.x/?geocode={}&kind=locality&apikey={}&format=json",
+            self.base_url, escaped_location, self.api_key
         );
 
-        let mut response = self.client.get(url.as_str()).send()?;
+        let mut response = self.client.send(|client| client.get(url.as_str()))?;
 
         match response.status() {
-            reqwest::StatusCode::OK => Ok(response.json()?),
+            reqwest::StatusCode::OK => response
+                .json()
+                .map_err(|error| SimpleError::new(http::error_message(&error)).into()),
             status => bail!("Unexpected HTTP status {}", status.as_u16()),
         }
     }
@@ -121,3 +130,23 @@ struct GeoObject {
 struct Point {
     pos: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::config;
+    use super::*;
+
+    #[test]
+    fn transport_error_hides_api_key() {
+        let client = HttpClient::new(&config::Http {
+            max_retries: 0,
+            ..config::Http::default()
+        });
+        // Connections to port 0 are refused.
+        let geocoder = Geocoder::new("secret-key", "http://127.0.0.1:0", client);
+
+        let error = geocoder.geocode("Москва").unwrap_err();
+        assert!(!error.to_string().is_empty());
+        assert!(!error.to_string().contains("secret-key"));
+    }
+}
@@ -0,0 +1,314 @@
+use std::cell::{Cell, RefCell};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use rand::Rng;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+
+use super::config;
+
+thread_local! {
+    // Number of retries made by clients in the current thread, see `with_retries`.
+    static RETRIES: Cell<u32> = const { Cell::new(0) };
+    // Takes a quota token for a request sent by clients in the current thread, see `with_quota`.
+    static TAKE_QUOTA_TOKEN: RefCell<Option<Box<dyn Fn() -> bool>>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` and returns its result along with the number of retries made by clients
+/// in the current thread meanwhile.
+pub fn with_retries<T, F: FnOnce() -> T>(f: F) -> (T, u32) {
+    // Retries left over by a previous job on the thread, e.g. a panicked one, don't count.
+    RETRIES.with(|retries| retries.set(0));
+    let result = f();
+    (result, RETRIES.with(|retries| retries.replace(0)))
+}
+
+/// Runs `f` taking a token with `take_token` for every request sent by clients in the current
+/// thread, retries included, except for the first one which the caller has paid for.
+/// Requests beyond the quota fail with `HttpError::QuotaExceeded` and retries beyond it
+/// are given up.
+pub fn with_quota<T, F, R>(take_token: F, f: R) -> T
+where
+    F: Fn() -> bool + 'static,
+    R: FnOnce() -> T,
+{
+    // Resets the check even if `f` panics since the thread may be reused.
+    struct Reset;
+
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            TAKE_QUOTA_TOKEN.with(|take| take.borrow_mut().take());
+        }
+    }
+
+    let prepaid = Cell::new(true);
+    let take_token = move || prepaid.replace(false) || take_token();
+    TAKE_QUOTA_TOKEN.with(|take| *take.borrow_mut() = Some(Box::new(take_token)));
+    let _reset = Reset;
+    f()
+}
+
+/// Takes a quota token for the next request. Always succeeds outside of `with_quota`.
+fn take_quota_token() -> bool {
+    TAKE_QUOTA_TOKEN.with(|take| take.borrow().as_ref().is_none_or(|take| take()))
+}
+
+/// Describes the request error without its URL since upstream URLs may carry API keys.
+pub fn error_message(error: &reqwest::Error) -> String {
+    let message = error.to_string();
+
+    match error.url() {
+        Some(url) => message.replace(&format!("{}: ", url), ""),
+        None => message,
+    }
+}
+
+/// A failure of a request sent through `HttpClient`.
+#[derive(Debug)]
+pub enum HttpError {
+    /// The request couldn't be sent or its response couldn't be received.
+    Request(reqwest::Error),
+    /// The request isn't sent since the quota is spent.
+    QuotaExceeded,
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HttpError::Request(error) => write!(f, "{}", error_message(error)),
+            HttpError::QuotaExceeded => write!(f, "Quota is exceeded"),
+        }
+    }
+}
+
+impl Error for HttpError {}
+
+impl From<reqwest::Error> for HttpError {
+    fn from(error: reqwest::Error) -> Self {
+        HttpError::Request(error)
+    }
+}
+
+/// HTTP client for upstream APIs with timeouts and retries of transient failures.
+///
+/// Only idempotent requests should be sent through it since they may be repeated.
+/// Retried failures are 5xx responses, 429 responses (waiting for `Retry-After` when given)
+/// and connection errors. Retries are delayed with exponential backoff and random jitter.
+pub struct HttpClient {
+    client: Client,
+    max_retries: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl HttpClient {
+    pub fn new(http_config: &config::Http) -> Self {
+        let client = Client::builder()
+            .connect_timeout(Duration::from_millis(http_config.connect_timeout_ms))
+            .timeout(Duration::from_millis(http_config.read_timeout_ms))
+            .build()
+            .expect("Failed to build HTTP client");
+
+        Self {
+            client,
+            max_retries: http_config.max_retries,
+            backoff: Duration::from_millis(http_config.backoff_ms),
+            max_backoff: Duration::from_millis(http_config.max_backoff_ms),
+        }
+    }
+
+    /// Sends the request built by `build_request` retrying transient failures.
+    /// Returns the last response or error when out of retries. Each attempt takes
+    /// a quota token within `with_quota`.
+    pub fn send<F>(&self, build_request: F) -> Result<Response, HttpError>
+    where
+        F: Fn(&Client) -> RequestBuilder,
+    {
+        let mut attempt = 0;
+
+        if !take_quota_token() {
+            return Err(HttpError::QuotaExceeded);
+        }
+
+        loop {
+            let result = build_request(&self.client).send();
+
+            let delay = match result {
+                Ok(ref response) => self.response_retry_delay(response, attempt),
+                Err(ref error) if is_transient(error) => Some(self.backoff_delay(attempt)),
+                Err(_) => None,
+            };
+
+            match delay {
+                Some(delay) if attempt < self.max_retries && take_quota_token() => {
+                    attempt += 1;
+                    RETRIES.with(|retries| retries.set(retries.get() + 1));
+                    thread::sleep(delay);
+                }
+                _ => return Ok(result?),
+            }
+        }
+    }
+
+    /// Returns the delay before the retry if the response is worth retrying.
+    fn response_retry_delay(&self, response: &Response, attempt: u32) -> Option<Duration> {
+        let status = response.status();
+
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            match retry_after(response) {
+                // Don't wait longer than the backoff limit, give up instead.
+                Some(delay) if delay > self.max_backoff => None,
+                Some(delay) => Some(delay),
+                None => Some(self.backoff_delay(attempt)),
+            }
+        } else if status.is_server_error() {
+            Some(self.backoff_delay(attempt))
+        } else {
+            None
+        }
+    }
+
+    /// Exponential backoff with jitter: a random delay between the half and the full
+    /// `backoff * 2^attempt` limited by `max_backoff`.
+    fn backoff_delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .backoff
+            .checked_mul(2u32.saturating_pow(attempt))
+            .map_or(self.max_backoff, |delay| delay.min(self.max_backoff));
+
+        let ms = delay.as_millis() as u64;
+        Duration::from_millis(rand::thread_rng().gen_range(ms / 2, ms + 1))
+    }
+}
+
+/// Parses `Retry-After` header given in seconds.
+fn retry_after(response: &Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
+
+/// Tells whether the error is a connection failure that may go away on retry.
+fn is_transient(error: &reqwest::Error) -> bool {
+    if error.is_timeout() {
+        return false;
+    }
+
+    match error.get_ref() {
+        Some(inner) => match inner.downcast_ref::<hyper::Error>() {
+            Some(hyper_error) => {
+                hyper_error.is_connect()
+                    || hyper_error.is_closed()
+                    || hyper_error
+                        .cause2()
+                        .and_then(|cause| cause.downcast_ref::<io::Error>())
+                        .is_some_and(is_connection_reset)
+            }
+            None => inner
+                .downcast_ref::<io::Error>()
+                .is_some_and(is_connection_reset),
+        },
+        None => false,
+    }
+}
+
+fn is_connection_reset(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use iron::{status, Iron};
+
+    use super::*;
+
+    fn client() -> HttpClient {
+        HttpClient::new(&config::Http {
+            backoff_ms: 100,
+            max_backoff_ms: 300,
+            ..config::Http::default()
+        })
+    }
+
+    #[test]
+    fn backoff_delay() {
+        let client = client();
+
+        for _ in 0..10 {
+            let first = client.backoff_delay(0).as_millis();
+            assert!((50..=100).contains(&first));
+            let second = client.backoff_delay(1).as_millis();
+            assert!((100..=200).contains(&second));
+            // Limited by `max_backoff`.
+            let tenth = client.backoff_delay(10).as_millis();
+            assert!((150..=300).contains(&tenth));
+        }
+    }
+
+    #[test]
+    fn requests_take_quota_tokens() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&requests);
+        let mut upstream = Iron::new(move |_: &mut iron::Request| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(iron::Response::with(status::InternalServerError))
+        })
+        .http("127.0.0.1:0")
+        .unwrap();
+        let url = format!("http://{}/", upstream.socket);
+
+        let client = HttpClient::new(&config::Http {
+            max_retries: 5,
+            backoff_ms: 1,
+            max_backoff_ms: 1,
+            ..config::Http::default()
+        });
+
+        // The first request is paid for, the tokens are enough for two retries only.
+        let tokens = Cell::new(2);
+        let take_token = move || tokens.get() > 0 && tokens.replace(tokens.get() - 1) > 0;
+        let (response, retries) =
+            with_retries(|| with_quota(take_token, || client.send(|client| client.get(&url))));
+        assert_eq!(
+            response.unwrap().status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        assert_eq!(retries, 2);
+
+        // Another request within the same call isn't sent.
+        let result = with_quota(
+            || false,
+            || {
+                client.send(|client| client.get(&url)).unwrap();
+                client.send(|client| client.get(&url))
+            },
+        );
+        assert!(matches!(result, Err(HttpError::QuotaExceeded)));
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+
+        // Requests outside of `with_quota` aren't limited.
+        client.send(|client| client.get(&url)).unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 10);
+
+        // Retries made before don't count.
+        assert_eq!(with_retries(|| ()).1, 0);
+
+        upstream.close().unwrap();
+    }
+}
@@ -1,7 +1,6 @@
 #[macro_use]
 extern crate simple_error;
 extern crate chrono;
-extern crate futures;
 extern crate iron;
 extern crate percent_encoding;
 extern crate router;
@@ -11,6 +10,7 @@ extern crate serde_qs;
 
 pub mod config;
 mod geocoder;
+mod http;
 mod provider;
 mod reporter;
 mod server;
@@ -0,0 +1,257 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use super::super::config;
+use super::ProviderError;
+
+/// Stops calling a provider after `failure_threshold` consecutive failures.
+///
+/// The open breaker skips the provider for `open_duration`. Then it turns half-open
+/// and lets a single probe call through: success closes the breaker, failure opens it again.
+/// Calls that don't reach the upstream, e.g. throttled ones, tell nothing about its health
+/// and don't count.
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    open_duration: Duration,
+    state: Mutex<State>,
+}
+
+/// Permission to call the provider given by `try_acquire`. Dropping the permit without
+/// recording the outcome, e.g. when the call panics, counts as a failure.
+pub struct Permit<'a> {
+    breaker: &'a CircuitBreaker,
+    done: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen,
+}
+
+/// Breaker state for the admin endpoint.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed { consecutive_failures: u32 },
+    Open { retry_in_ms: u64 },
+    HalfOpen,
+}
+
+impl CircuitBreaker {
+    pub fn new(breaker_config: &config::CircuitBreaker) -> Self {
+        Self {
+            failure_threshold: breaker_config.failure_threshold,
+            open_duration: Duration::from_millis(breaker_config.open_ms),
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    /// Returns the permission to call the provider now if any. Turns the open breaker
+    /// half-open when its time is up and lets the caller make the probe.
+    pub fn try_acquire(&self) -> Option<Permit<'_>> {
+        let mut state = self.state.lock().unwrap();
+
+        let allowed = match *state {
+            State::Closed { .. } => true,
+            State::Open { until } if Instant::now() >= until => {
+                *state = State::HalfOpen;
+                true
+            }
+            // The probe is in flight.
+            State::Open { .. } | State::HalfOpen => false,
+        };
+
+        if allowed {
+            Some(Permit {
+                breaker: self,
+                done: false,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// The half-open breaker lets the next call make the probe.
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        if *state == State::HalfOpen {
+            *state = State::Open {
+                until: Instant::now(),
+            };
+        }
+    }
+
+    fn record(&self, success: bool) {
+        let mut state = self.state.lock().unwrap();
+
+        *state = match (*state, success) {
+            (_, true) => State::Closed { failures: 0 },
+            (State::Closed { failures }, false) if failures + 1 < self.failure_threshold => {
+                State::Closed {
+                    failures: failures + 1,
+                }
+            }
+            (_, false) => State::Open {
+                until: Instant::now() + self.open_duration,
+            },
+        };
+    }
+
+    pub fn state(&self) -> CircuitState {
+        match *self.state.lock().unwrap() {
+            State::Closed { failures } => CircuitState::Closed {
+                consecutive_failures: failures,
+            },
+            State::Open { until } => CircuitState::Open {
+                retry_in_ms: until.saturating_duration_since(Instant::now()).as_millis() as u64,
+            },
+            State::HalfOpen => CircuitState::HalfOpen,
+        }
+    }
+}
+
+impl Permit<'_> {
+    /// Records the outcome of the call. Throttled or declined calls only give back
+    /// the permission like `release`.
+    pub fn record<T>(mut self, result: &Result<T, ProviderError>) {
+        self.done = true;
+
+        match result {
+            Ok(_) => self.breaker.record(true),
+            Err(error) if error.is_failure() => self.breaker.record(false),
+            Err(_) => self.breaker.release(),
+        }
+    }
+
+    /// Gives back the permission when the call isn't made after all.
+    pub fn release(mut self) {
+        self.done = true;
+        self.breaker.release();
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.breaker.record(false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{self, AssertUnwindSafe};
+    use std::thread;
+
+    use super::*;
+
+    fn breaker(open_ms: u64) -> CircuitBreaker {
+        CircuitBreaker::new(&config::CircuitBreaker {
+            failure_threshold: 2,
+            open_ms,
+        })
+    }
+
+    fn success() -> Result<(), ProviderError> {
+        Ok(())
+    }
+
+    fn failure() -> Result<(), ProviderError> {
+        Err(ProviderError::Http(String::from(
+            "Unexpected HTTP status 500",
+        )))
+    }
+
+    #[test]
+    fn open_after_consecutive_failures() {
+        let breaker = breaker(60_000);
+        breaker.try_acquire().unwrap().record(&failure());
+        breaker.try_acquire().unwrap().record(&success());
+        breaker.try_acquire().unwrap().record(&failure());
+        // A throttled call doesn't count.
+        breaker
+            .try_acquire()
+            .unwrap()
+            .record(&Err::<(), _>(ProviderError::Throttled));
+        assert_eq!(
+            breaker.state(),
+            CircuitState::Closed {
+                consecutive_failures: 1
+            }
+        );
+
+        breaker.try_acquire().unwrap().record(&failure());
+        assert!(breaker.try_acquire().is_none());
+
+        match breaker.state() {
+            CircuitState::Open { retry_in_ms } => assert!(retry_in_ms > 0),
+            state => panic!("Expected open state, got {:?}", state),
+        }
+    }
+
+    #[test]
+    fn half_open_probe() {
+        let breaker = breaker(10);
+        breaker.record(false);
+        breaker.record(false);
+        thread::sleep(Duration::from_millis(20));
+
+        // Only a single probe is let through.
+        let probe = breaker.try_acquire().unwrap();
+        assert!(breaker.try_acquire().is_none());
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+
+        // A failed probe opens the breaker again.
+        probe.record(&failure());
+        assert!(breaker.try_acquire().is_none());
+        thread::sleep(Duration::from_millis(20));
+
+        // A released probe is given to the next caller, so are probes that didn't reach
+        // the upstream.
+        breaker.try_acquire().unwrap().release();
+        breaker
+            .try_acquire()
+            .unwrap()
+            .record(&Err::<(), _>(ProviderError::Throttled));
+        breaker
+            .try_acquire()
+            .unwrap()
+            .record(&Err::<(), _>(ProviderError::OutOfCoverage));
+        assert_eq!(breaker.state(), CircuitState::Open { retry_in_ms: 0 });
+
+        // A successful probe closes it.
+        breaker.try_acquire().unwrap().record(&success());
+        assert_eq!(
+            breaker.state(),
+            CircuitState::Closed {
+                consecutive_failures: 0
+            }
+        );
+    }
+
+    #[test]
+    fn panicked_probe_fails() {
+        let breaker = breaker(100);
+        breaker.record(false);
+        breaker.record(false);
+        thread::sleep(Duration::from_millis(120));
+
+        let probe = breaker.try_acquire().unwrap();
+        let result = panic::catch_unwind(AssertUnwindSafe(move || {
+            let _probe = probe;
+            panic!("Provider failure");
+        }));
+        assert!(result.is_err());
+
+        // The breaker is open again rather than stuck half-open.
+        match breaker.state() {
+            CircuitState::Open { retry_in_ms } => assert!(retry_in_ms > 0),
+            state => panic!("Expected open state, got {:?}", state),
+        }
+    }
+}
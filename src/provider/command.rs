@@ -0,0 +1,245 @@
+use std::io::{Read, Write};
+use std::process::{Child, Command as Process, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+
+use super::super::provider::{Provider, ProviderError};
+use super::super::reporter::ProviderForecast;
+use super::daily::DailyRecord;
+
+// Interval of checking whether the process has exited.
+const POLL_INTERVAL_MS: u64 = 10;
+// Number of last characters of stderr kept in error messages.
+const STDERR_TAIL_CHARS: usize = 200;
+
+/// Provider backed by an external executable.
+///
+/// The process gets a JSON request `{"lat": 55.75, "lon": 37.62, "days": 5}` on stdin
+/// and has to print a JSON array of daily reports (see `DailyRecord`) to stdout and exit
+/// with zero status.
+/// The process is killed when it doesn't exit within `timeout`. Its output isn't waited for
+/// past `timeout` either, e.g. when the process leaves a child holding stdout open.
+pub struct Command {
+    title: String,
+    program: String,
+    args: Vec<String>,
+    timeout: Duration,
+}
+
+impl Command {
+    pub fn new(title: &str, program: &str, args: &[String], timeout: Duration) -> Self {
+        Self {
+            title: String::from(title),
+            program: String::from(program),
+            args: args.to_vec(),
+            timeout,
+        }
+    }
+
+    /// Runs the process with the request on stdin and returns its stdout.
+    fn run(&self, request: &[u8]) -> Result<Vec<u8>, ProviderError> {
+        let deadline = Instant::now() + self.timeout;
+        let timeout_error = || {
+            ProviderError::Timeout(format!(
+                "Command didn't finish in {} ms",
+                self.timeout.as_millis()
+            ))
+        };
+
+        let mut child = Process::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|error| {
+                ProviderError::Process(format!("Couldn't run {}: {}", self.program, error))
+            })?;
+
+        // Pipes are served in threads so that a process filling one of them doesn't block.
+        let mut stdin = child.stdin.take().unwrap();
+        let request = request.to_vec();
+        thread::spawn(move || stdin.write_all(&request));
+        let stdout = read_in_thread(child.stdout.take().unwrap());
+        let stderr = read_in_thread(child.stderr.take().unwrap());
+
+        let status = wait_until(&mut child, deadline)?.ok_or_else(|| {
+            child.kill().ok();
+            child.wait().ok();
+            timeout_error()
+        })?;
+
+        let output = stdout
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            .map_err(|_| timeout_error())?;
+
+        if status.success() {
+            Ok(output)
+        } else {
+            let stderr = stderr
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                .unwrap_or_default();
+            let message = String::from_utf8_lossy(&stderr);
+            Err(ProviderError::Process(format!(
+                "Command failed with {}: {}",
+                status,
+                tail(message.trim(), STDERR_TAIL_CHARS)
+            )))
+        }
+    }
+}
+
+impl Provider for Command {
+    fn name(&self) -> String {
+        self.title.clone()
+    }
+
+    /// Gets daily reports from the process and checks them against the schema.
+    fn get_reports(
+        &self,
+        _api_key: &str,
+        lat: f32,
+        lon: f32,
+        days: usize,
+    ) -> Result<ProviderForecast, ProviderError> {
+        let request = json!({ "lat": lat, "lon": lon, "days": days });
+        let output = self.run(request.to_string().as_bytes())?;
+        let records: Vec<DailyRecord> = serde_json::from_slice(&output)?;
+
+        let forecasts = records
+            .into_iter()
+            .take(days)
+            .map(DailyRecord::daily_forecast)
+            .collect::<Result<_, _>>()?;
+
+        Ok(ProviderForecast::new(forecasts))
+    }
+}
+
+/// Reads the pipe to the end in a thread and sends the contents to the returned receiver.
+fn read_in_thread<R: Read + Send + 'static>(mut pipe: R) -> mpsc::Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        pipe.read_to_end(&mut buffer).ok();
+        sender.send(buffer).ok();
+    });
+
+    receiver
+}
+
+/// Waits for the process to exit. Returns `None` when it's still running at `deadline`.
+fn wait_until(
+    child: &mut Child,
+    deadline: Instant,
+) -> Result<Option<std::process::ExitStatus>, ProviderError> {
+    loop {
+        let status = child
+            .try_wait()
+            .map_err(|error| ProviderError::Process(error.to_string()))?;
+
+        if status.is_some() || Instant::now() >= deadline {
+            return Ok(status);
+        }
+
+        thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+    }
+}
+
+/// Takes up to `max_chars` last characters of the message.
+fn tail(message: &str, max_chars: usize) -> String {
+    let chars = message.chars().count();
+
+    if chars <= max_chars {
+        String::from(message)
+    } else {
+        let tail: String = message.chars().skip(chars - max_chars).collect();
+        format!("…{}", tail)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::super::super::reporter::Condition;
+    use super::*;
+
+    fn command(script: &str, timeout_ms: u64) -> Command {
+        let args = vec![String::from("-c"), String::from(script)];
+        Command::new("Model", "sh", &args, Duration::from_millis(timeout_ms))
+    }
+
+    #[test]
+    fn reports_from_stdout() {
+        // Echoes the number of days from the request as the temperature.
+        let script = r#"
+            days=$(sed 's/.*"days":\([0-9]*\).*/\1/')
+            echo "[{\"date\": \"2019-03-11\", \"temperature\": $days, \"condition\": \"fog\"},"
+            echo " {\"date\": \"2019-03-12\", \"temperature\": 1.5}]"
+        "#;
+
+        let reports = command(script, 5_000)
+            .get_reports("", 55.75, 37.62, 1)
+            .unwrap()
+            .forecasts;
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].date, NaiveDate::from_ymd(2019, 3, 11));
+        assert_eq!(reports[0].report.temperature, 1.0);
+        assert_eq!(reports[0].report.condition, Some(Condition::Fog));
+    }
+
+    #[test]
+    fn schema_violations() {
+        let unknown_field = r#"echo '[{"date": "2019-03-11", "temperature": 1, "temp": 2}]'"#;
+        let error = command(unknown_field, 5_000)
+            .get_reports("", 0.0, 0.0, 1)
+            .unwrap_err();
+        assert!(matches!(error, ProviderError::Parse(_)));
+
+        let unknown_condition =
+            r#"echo '[{"date": "2019-03-11", "temperature": 1, "condition": "volcano"}]'"#;
+        let error = command(unknown_condition, 5_000)
+            .get_reports("", 0.0, 0.0, 1)
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Unknown condition code \"volcano\"");
+    }
+
+    #[test]
+    fn process_failures() {
+        let error = command("echo 'No model' >&2; exit 3", 5_000)
+            .get_reports("", 0.0, 0.0, 1)
+            .unwrap_err();
+        assert!(matches!(error, ProviderError::Process(_)));
+        assert!(error.to_string().ends_with("No model"));
+
+        let started_at = Instant::now();
+        let error = command("sleep 5", 100)
+            .get_reports("", 0.0, 0.0, 1)
+            .unwrap_err();
+        assert!(matches!(error, ProviderError::Timeout(_)));
+        assert!(started_at.elapsed() < Duration::from_secs(5));
+
+        // Only the tail of a long stderr is kept.
+        let error = command("printf '%0500d' 0 >&2; echo 'No model' >&2; exit 3", 5_000)
+            .get_reports("", 0.0, 0.0, 1)
+            .unwrap_err();
+        assert!(error.to_string().ends_with("No model"));
+        assert!(error.to_string().len() < 300);
+    }
+
+    #[test]
+    fn child_holding_output() {
+        // The background child keeps stdout open after the process exits.
+        let started_at = Instant::now();
+        let error = command("sleep 5 & echo '[]'", 200)
+            .get_reports("", 0.0, 0.0, 1)
+            .unwrap_err();
+        assert!(matches!(error, ProviderError::Timeout(_)));
+        assert!(started_at.elapsed() < Duration::from_secs(5));
+    }
+}
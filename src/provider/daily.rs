@@ -0,0 +1,241 @@
+use std::collections::BTreeMap;
+
+use chrono::prelude::*;
+use serde::Deserialize;
+
+use super::super::provider::ProviderError;
+use super::super::reporter::{
+    circular_mean, AggregationStrategy, Condition, DailyForecast, Report, Sample,
+};
+
+// Local hours of the daytime part to line up with Yandex's `parts.day` (12:00–18:00).
+const DAYTIME_START_HOUR: u32 = 12;
+const DAYTIME_END_HOUR: u32 = 18;
+
+/// A forecast for a part of a day like an hour or a 3-hour interval.
+/// The report holds the temperature at the slot and the precipitation amount within the slot.
+pub struct Slot {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub report: Report,
+}
+
+/// Daily report in the schema shared by local providers like `Command` and `File`.
+/// Units are the same as of `Report`, the condition is a code like "light_rain".
+/// Unknown fields are rejected.
+///
+/// Example: `{"date": "2019-03-11", "temperature": -10.5, "condition": "light_snow"}`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DailyRecord {
+    pub date: NaiveDate,
+    pub temperature: f32,
+    pub temperature_min: Option<f32>,
+    pub temperature_max: Option<f32>,
+    pub humidity: Option<f32>,
+    pub pressure: Option<f32>,
+    pub wind_speed: Option<f32>,
+    pub wind_direction: Option<f32>,
+    pub wind_gust: Option<f32>,
+    pub precipitation: Option<f32>,
+    pub precipitation_probability: Option<f32>,
+    pub cloud_cover: Option<f32>,
+    pub condition: Option<String>,
+}
+
+impl DailyRecord {
+    /// Converts the record to a forecast. Fails on unknown condition codes.
+    pub fn daily_forecast(self) -> Result<DailyForecast, ProviderError> {
+        let condition = match &self.condition {
+            Some(code) => Some(Condition::from_code(code).ok_or_else(|| {
+                ProviderError::Parse(format!("Unknown condition code \"{}\"", code))
+            })?),
+            None => None,
+        };
+
+        let report = Report {
+            temperature_min: self.temperature_min,
+            temperature_max: self.temperature_max,
+            humidity: self.humidity,
+            pressure: self.pressure,
+            wind_speed: self.wind_speed,
+            wind_direction: self.wind_direction,
+            wind_gust: self.wind_gust,
+            precipitation: self.precipitation,
+            precipitation_probability: self.precipitation_probability,
+            cloud_cover: self.cloud_cover,
+            condition,
+            ..Report::new(self.temperature)
+        };
+
+        Ok(DailyForecast::new(self.date, report))
+    }
+}
+
+/// Approximates the UTC offset of a location by its longitude when the actual time zone
+/// is unknown: 15° per hour.
+pub fn estimated_utc_offset(lon: f32) -> FixedOffset {
+    FixedOffset::east((lon / 15.0).round() as i32 * 3600)
+}
+
+/// Folds slots into calendar days in the time zone with the given `offset`.
+///
+/// Instant values (temperature, humidity, pressure, wind, cloud cover and condition) are taken
+/// from daytime slots falling back to all slots of the day when the day has no daytime slots
+/// (e.g. today's evening). Min and max temperatures, gusts, precipitation amount
+/// and probability are taken over the whole day.
+pub fn fold_daily(slots: Vec<Slot>, offset: FixedOffset) -> Vec<DailyForecast> {
+    let mut days: BTreeMap<NaiveDate, Vec<(Slot, bool)>> = BTreeMap::new();
+
+    for slot in slots {
+        let local_start = slot.start.with_timezone(&offset);
+        let is_daytime = (DAYTIME_START_HOUR..DAYTIME_END_HOUR).contains(&local_start.hour());
+
+        days.entry(local_start.date().naive_local())
+            .or_default()
+            .push((slot, is_daytime));
+    }
+
+    days.into_iter()
+        .map(|(date, day_slots)| fold_day(date, &day_slots))
+        .collect()
+}
+
+fn fold_day(date: NaiveDate, day_slots: &[(Slot, bool)]) -> DailyForecast {
+    let all = day_slots.iter().map(|(slot, _)| slot).collect::<Vec<_>>();
+
+    let daytime = day_slots
+        .iter()
+        .filter(|(_, is_daytime)| *is_daytime)
+        .map(|(slot, _)| slot)
+        .collect::<Vec<_>>();
+
+    let instant = if daytime.is_empty() { &all } else { &daytime };
+    let mean = |field: fn(&Report) -> Option<f32>| {
+        AggregationStrategy::Mean.aggregate(&samples(instant, field))
+    };
+
+    let report = Report {
+        temperature_min: min(&samples(&all, |r| {
+            r.temperature_min.or(Some(r.temperature))
+        })),
+        temperature_max: max(&samples(&all, |r| {
+            r.temperature_max.or(Some(r.temperature))
+        })),
+        humidity: mean(|r| r.humidity),
+        pressure: mean(|r| r.pressure),
+        wind_speed: mean(|r| r.wind_speed),
+        wind_direction: circular_mean(&samples(instant, |r| r.wind_direction)),
+        wind_gust: max(&samples(&all, |r| r.wind_gust)),
+        precipitation: sum(&samples(&all, |r| r.precipitation)),
+        precipitation_probability: max(&samples(&all, |r| r.precipitation_probability)),
+        cloud_cover: mean(|r| r.cloud_cover),
+        condition: most_common_condition(instant),
+        ..Report::new(mean(|r| Some(r.temperature)).unwrap_or(f32::NAN))
+    };
+
+    DailyForecast {
+        date,
+        period: Some((all[0].start, all[all.len() - 1].end)),
+        report,
+    }
+}
+
+fn samples<F>(slots: &[&Slot], field: F) -> Vec<Sample>
+where
+    F: Fn(&Report) -> Option<f32>,
+{
+    slots
+        .iter()
+        .filter_map(|slot| field(&slot.report).map(|value| Sample::new(value, 1.0)))
+        .collect()
+}
+
+fn sum(samples: &[Sample]) -> Option<f32> {
+    if samples.is_empty() {
+        None
+    } else {
+        Some(samples.iter().map(|s| s.value).sum())
+    }
+}
+
+fn min(samples: &[Sample]) -> Option<f32> {
+    samples
+        .iter()
+        .map(|s| s.value)
+        .fold(None, |min: Option<f32>, x| {
+            Some(min.map_or(x, |min| min.min(x)))
+        })
+}
+
+fn max(samples: &[Sample]) -> Option<f32> {
+    samples
+        .iter()
+        .map(|s| s.value)
+        .fold(None, |max: Option<f32>, x| {
+            Some(max.map_or(x, |max| max.max(x)))
+        })
+}
+
+/// Returns the condition of the most slots. Ties are resolved in favour of the more severe one.
+fn most_common_condition(slots: &[&Slot]) -> Option<Condition> {
+    Condition::consensus(
+        slots
+            .iter()
+            .filter_map(|slot| slot.report.condition.map(|condition| (condition, 1.0))),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    fn slot(time: &str, temperature: f32) -> Slot {
+        let start = DateTime::parse_from_rfc3339(time)
+            .unwrap()
+            .with_timezone(&Utc);
+
+        Slot {
+            start,
+            end: start + Duration::hours(3),
+            report: Report::new(temperature),
+        }
+    }
+
+    #[test]
+    fn fold_daily_in_local_time_zone() {
+        // UTC+3, so 21:00 UTC is the next local day.
+        let slots = vec![
+            slot("2019-03-11T15:00:00Z", -2.0),
+            slot("2019-03-11T18:00:00Z", -4.0),
+            slot("2019-03-11T21:00:00Z", -6.0),
+            slot("2019-03-12T06:00:00Z", -1.0),
+            slot("2019-03-12T09:00:00Z", 1.0),
+            slot("2019-03-12T12:00:00Z", 3.0),
+            slot("2019-03-12T15:00:00Z", -3.0),
+        ];
+
+        let forecasts = fold_daily(slots, FixedOffset::east(3 * 3600));
+        assert_eq!(forecasts.len(), 2);
+
+        // Evening only: no daytime slots so the mean of all slots is used.
+        let first = &forecasts[0];
+        assert_eq!(first.date, NaiveDate::from_ymd(2019, 3, 11));
+        assert_eq!(first.report.temperature, -3.0);
+        assert_eq!(first.report.temperature_min, Some(-4.0));
+        assert_eq!(first.report.temperature_max, Some(-2.0));
+
+        // Daytime slots are 12:00 and 15:00 local (09:00 and 12:00 UTC).
+        let second = &forecasts[1];
+        assert_eq!(second.date, NaiveDate::from_ymd(2019, 3, 12));
+        assert_eq!(second.report.temperature, 2.0);
+        assert_eq!(second.report.temperature_min, Some(-6.0));
+        assert_eq!(second.report.temperature_max, Some(3.0));
+
+        let (start, end) = second.period.unwrap();
+        assert_eq!(start.to_rfc3339(), "2019-03-11T21:00:00+00:00");
+        assert_eq!(end.to_rfc3339(), "2019-03-12T18:00:00+00:00");
+    }
+}
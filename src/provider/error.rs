@@ -0,0 +1,99 @@
+use std::error::Error;
+use std::fmt;
+
+use super::super::http::{self, HttpError};
+
+/// A failure of a provider call, classified by its cause.
+#[derive(Debug)]
+pub enum ProviderError {
+    /// The upstream didn't respond in time.
+    Timeout(String),
+    /// The upstream couldn't be reached or responded with an unexpected HTTP status.
+    Http(String),
+    /// The upstream rejected the API key or its quota is spent (HTTP 401, 403 or 429).
+    KeyRejected(String),
+    /// The upstream responded with a payload that couldn't be parsed.
+    Parse(String),
+    /// The provider's process couldn't be run or exited with an error.
+    Process(String),
+    /// The provider's local data couldn't be read.
+    Io(String),
+    /// The provider is skipped since its circuit breaker is open.
+    CircuitOpen,
+    /// The provider is skipped since its quota is spent.
+    Throttled,
+    /// The provider is skipped since all workers are busy with other calls.
+    Busy,
+    /// The provider declined since it doesn't cover the location. Not a failure.
+    OutOfCoverage,
+}
+
+impl ProviderError {
+    /// Tells whether the error is a failure of the provider rather than a decline
+    /// or a spent quota.
+    pub fn is_failure(&self) -> bool {
+        !matches!(
+            self,
+            ProviderError::OutOfCoverage | ProviderError::Throttled
+        )
+    }
+
+    pub fn unexpected_status(status: reqwest::StatusCode) -> Self {
+        let message = format!("Unexpected HTTP status {}", status.as_u16());
+
+        match status {
+            reqwest::StatusCode::UNAUTHORIZED
+            | reqwest::StatusCode::FORBIDDEN
+            | reqwest::StatusCode::TOO_MANY_REQUESTS => ProviderError::KeyRejected(message),
+            _ => ProviderError::Http(message),
+        }
+    }
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProviderError::Timeout(message)
+            | ProviderError::Http(message)
+            | ProviderError::KeyRejected(message)
+            | ProviderError::Parse(message)
+            | ProviderError::Process(message)
+            | ProviderError::Io(message) => write!(f, "{}", message),
+            ProviderError::CircuitOpen => write!(f, "Circuit breaker is open"),
+            ProviderError::Throttled => write!(f, "Quota is exceeded"),
+            ProviderError::Busy => write!(f, "All workers are busy"),
+            ProviderError::OutOfCoverage => write!(f, "Location is out of coverage"),
+        }
+    }
+}
+
+impl Error for ProviderError {}
+
+impl From<reqwest::Error> for ProviderError {
+    fn from(error: reqwest::Error) -> Self {
+        let message = http::error_message(&error);
+
+        if error.is_timeout() {
+            ProviderError::Timeout(message)
+        } else if error.is_serialization() {
+            ProviderError::Parse(message)
+        } else {
+            ProviderError::Http(message)
+        }
+    }
+}
+
+impl From<HttpError> for ProviderError {
+    fn from(error: HttpError) -> Self {
+        match error {
+            HttpError::Request(error) => ProviderError::from(error),
+            HttpError::QuotaExceeded => ProviderError::Throttled,
+        }
+    }
+}
+
+impl From<serde_json::Error> for ProviderError {
+    fn from(error: serde_json::Error) -> Self {
+        ProviderError::Parse(error.to_string())
+    }
+}
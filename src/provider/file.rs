@@ -0,0 +1,417 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use chrono::prelude::*;
+use serde::Deserialize;
+use serde_json::{Number, Value};
+
+use super::super::provider::{estimated_utc_offset, Provider, ProviderError};
+use super::super::reporter::{DailyForecast, ProviderForecast};
+use super::daily::DailyRecord;
+
+/// Provider of forecasts from a local CSV or NDJSON file or a directory of such files.
+///
+/// Each record is a daily report for a location: see `FileRecord`. A file is read again
+/// when its modification time changes.
+pub struct File {
+    title: String,
+    path: PathBuf,
+    max_distance: f32,
+    cache: Mutex<HashMap<PathBuf, CacheEntry>>,
+}
+
+struct CacheEntry {
+    modified: SystemTime,
+    records: Arc<Vec<Record>>,
+}
+
+/// A parsed record of a file.
+struct Record {
+    lat: f32,
+    lon: f32,
+    forecast: DailyForecast,
+}
+
+impl File {
+    pub fn new(title: &str, path: &str, max_distance: f32) -> Self {
+        Self {
+            title: String::from(title),
+            path: PathBuf::from(path),
+            max_distance,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns records of all the files, the least recently modified ones first.
+    /// Files that are new or modified since the last call are read again.
+    fn load(&self) -> Result<Vec<Arc<Vec<Record>>>, ProviderError> {
+        let paths = if self.path.is_dir() {
+            let entries = fs::read_dir(&self.path).map_err(|error| io_error(&self.path, error))?;
+            let mut paths = Vec::new();
+
+            for entry in entries {
+                let path = entry.map_err(|error| io_error(&self.path, error))?.path();
+
+                if path.is_file() && Format::of(&path).is_some() {
+                    paths.push(path);
+                }
+            }
+
+            paths
+        } else {
+            vec![self.path.clone()]
+        };
+
+        let mut modified_times = Vec::with_capacity(paths.len());
+
+        for path in &paths {
+            let modified = fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .map_err(|error| io_error(path, error))?;
+
+            modified_times.push(modified);
+        }
+
+        let mut files = Vec::with_capacity(paths.len());
+        let mut stale = Vec::new();
+
+        {
+            let cache = self.cache.lock().unwrap();
+
+            for (path, modified) in paths.iter().zip(modified_times) {
+                match cache.get(path) {
+                    Some(entry) if entry.modified == modified => {
+                        files.push((modified, entry.records.clone()))
+                    }
+                    _ => stale.push((path.clone(), modified)),
+                }
+            }
+        }
+
+        // Files are parsed without holding the lock so that other calls don't wait for them.
+        let mut entries = Vec::with_capacity(stale.len());
+
+        for (path, modified) in stale {
+            let records = Arc::new(read(&path)?);
+            files.push((modified, records.clone()));
+            entries.push((path, CacheEntry { modified, records }));
+        }
+
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|path, _| paths.contains(path));
+
+        for (path, entry) in entries {
+            // A concurrent call may have read a newer version of the file meanwhile.
+            if cache
+                .get(&path)
+                .is_none_or(|cached| cached.modified <= entry.modified)
+            {
+                cache.insert(path, entry);
+            }
+        }
+
+        drop(cache);
+        files.sort_by_key(|(modified, _)| *modified);
+        Ok(files.into_iter().map(|(_, records)| records).collect())
+    }
+}
+
+impl Provider for File {
+    fn name(&self) -> String {
+        self.title.clone()
+    }
+
+    /// Picks records of the nearest location within `max_distance` degrees of the given one.
+    /// When several files have a report on the same day, the most recently modified one wins.
+    fn get_reports(
+        &self,
+        _api_key: &str,
+        lat: f32,
+        lon: f32,
+        days: usize,
+    ) -> Result<ProviderForecast, ProviderError> {
+        let files = self.load()?;
+        let records = files.iter().flat_map(|records| records.iter());
+
+        let distance = |record: &Record| (record.lat - lat).abs().max((record.lon - lon).abs());
+        let nearest = records
+            .clone()
+            .filter(|record| distance(record) <= self.max_distance)
+            .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+            .map(|record| (record.lat, record.lon))
+            .ok_or(ProviderError::OutOfCoverage)?;
+
+        // Files don't tell the location's time zone.
+        let offset = estimated_utc_offset(lon);
+        let today = Utc::now().with_timezone(&offset).naive_local().date();
+        let mut forecasts = BTreeMap::new();
+
+        for record in records {
+            if (record.lat, record.lon) == nearest && record.forecast.date >= today {
+                forecasts.insert(record.forecast.date, record.forecast.clone());
+            }
+        }
+
+        let forecasts = forecasts.into_values().take(days).collect();
+        Ok(ProviderForecast::new(forecasts))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Csv,
+    Ndjson,
+}
+
+impl Format {
+    /// Tells the format by the file's extension.
+    fn of(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "csv" => Some(Format::Csv),
+            "ndjson" | "jsonl" => Some(Format::Ndjson),
+            _ => None,
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<Record>, ProviderError> {
+    let parse_error = |error: &dyn std::error::Error| {
+        ProviderError::Parse(format!("{}: {}", path.display(), error))
+    };
+
+    let file = fs::File::open(path).map_err(|error| io_error(path, error))?;
+
+    let records: Vec<FileRecord> = match Format::of(path) {
+        Some(Format::Csv) => {
+            let mut reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(file);
+            let headers = reader
+                .headers()
+                .map_err(|error| parse_error(&error))?
+                .clone();
+            let mut records = Vec::new();
+
+            for row in reader.records() {
+                let row = row.map_err(|error| parse_error(&error))?;
+                let object = csv_object(&headers, &row);
+                records.push(serde_json::from_value(object).map_err(|error| parse_error(&error))?);
+            }
+
+            records
+        }
+        Some(Format::Ndjson) => {
+            let mut records = Vec::new();
+
+            for line in BufReader::new(file).lines() {
+                let line = line.map_err(|error| io_error(path, error))?;
+
+                if !line.trim().is_empty() {
+                    records.push(serde_json::from_str(&line).map_err(|error| parse_error(&error))?);
+                }
+            }
+
+            records
+        }
+        None => {
+            let message = format!("{}: unsupported file format", path.display());
+            return Err(ProviderError::Parse(message));
+        }
+    };
+
+    records
+        .into_iter()
+        .map(|record| record.record().map_err(|error| parse_error(&error)))
+        .collect()
+}
+
+/// Converts a CSV row to a JSON object so that both formats share the schema.
+/// Empty fields are left out and numeric ones are taken as numbers.
+fn csv_object(headers: &csv::StringRecord, row: &csv::StringRecord) -> Value {
+    let object = headers
+        .iter()
+        .zip(row.iter())
+        .filter(|(_, field)| !field.is_empty())
+        .map(|(header, field)| {
+            let value = field
+                .parse::<f64>()
+                .ok()
+                .and_then(Number::from_f64)
+                .map_or_else(|| Value::from(field), Value::Number);
+
+            (String::from(header), value)
+        })
+        .collect();
+
+    Value::Object(object)
+}
+
+fn io_error(path: &Path, error: std::io::Error) -> ProviderError {
+    ProviderError::Io(format!("{}: {}", path.display(), error))
+}
+
+/// Daily report for a location: `DailyRecord` fields along with the location's coordinates.
+/// Example CSV:
+///
+/// ```text
+/// lat,lon,date,temperature,humidity,condition
+/// 55.75,37.62,2019-03-11,-10.5,76,light_snow
+/// ```
+#[derive(Debug, Deserialize)]
+struct FileRecord {
+    lat: f32,
+    lon: f32,
+    #[serde(flatten)]
+    record: DailyRecord,
+    /// Fields left over by `record`. Flattening doesn't let it reject them itself.
+    #[serde(flatten)]
+    unknown: BTreeMap<String, Value>,
+}
+
+impl FileRecord {
+    fn record(self) -> Result<Record, ProviderError> {
+        if let Some(field) = self.unknown.keys().next() {
+            return Err(ProviderError::Parse(format!("Unknown field \"{}\"", field)));
+        }
+
+        Ok(Record {
+            lat: self.lat,
+            lon: self.lon,
+            forecast: self.record.daily_forecast()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::Duration as Days;
+
+    use super::super::super::reporter::Condition;
+    use super::*;
+
+    /// Creates an empty directory for the test's files.
+    fn directory(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("weather_report_{}_{}", name, std::process::id()));
+        fs::remove_dir_all(&path).ok();
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    /// Writes the file and sets its modification time to `modified` seconds since the epoch.
+    fn write(path: &Path, content: &str, modified: u64) {
+        fs::write(path, content).unwrap();
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(modified))
+            .unwrap();
+    }
+
+    /// Date at the test locations which are UTC+3 by their longitude.
+    fn day(offset: i64) -> NaiveDate {
+        let today = Utc::now().with_timezone(&FixedOffset::east(3 * 3600));
+        today.naive_local().date() + Days::days(offset)
+    }
+
+    #[test]
+    fn csv_reports() {
+        let path = directory("csv").join("forecast.csv");
+        let content = format!(
+            "lat, lon, date, temperature, humidity, condition\n\
+             55.75, 37.62, {yesterday}, -12, 80, snow\n\
+             55.75, 37.62, {tomorrow}, -8.5, , \n\
+             55.75, 37.62, {today}, -10.5, 76, light_snow\n\
+             55.70, 37.60, {today}, 1, , clear\n\
+             59.94, 30.31, {today}, -5, , \n",
+            yesterday = day(-1),
+            today = day(0),
+            tomorrow = day(1),
+        );
+
+        write(&path, &content, 1_000);
+        let provider = File::new("Model", path.to_str().unwrap(), 0.1);
+        assert_eq!(provider.name(), "Model");
+
+        let reports = provider.get_reports("", 55.76, 37.62, 5).unwrap().forecasts;
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].date, day(0));
+        assert_eq!(reports[0].report.temperature, -10.5);
+        assert_eq!(reports[0].report.humidity, Some(76.0));
+        assert_eq!(reports[0].report.condition, Some(Condition::LightSnow));
+        assert_eq!(reports[1].date, day(1));
+        assert_eq!(reports[1].report.humidity, None);
+        assert_eq!(reports[1].report.condition, None);
+
+        let reports = provider.get_reports("", 55.76, 37.62, 1).unwrap().forecasts;
+        assert_eq!(reports.len(), 1);
+
+        let error = provider.get_reports("", 40.71, -74.01, 5).unwrap_err();
+        assert!(matches!(error, ProviderError::OutOfCoverage));
+    }
+
+    #[test]
+    fn ndjson_directory_reload() {
+        let path = directory("ndjson");
+        let record = |temperature: f32| {
+            format!(
+                "{{\"lat\": 55.75, \"lon\": 37.62, \"date\": \"{}\", \"temperature\": {}}}\n",
+                day(0),
+                temperature
+            )
+        };
+
+        write(&path.join("a.ndjson"), &record(1.0), 1_000);
+        write(&path.join("b.jsonl"), &record(2.0), 2_000);
+        write(&path.join("notes.txt"), "not a forecast", 3_000);
+        let provider = File::new("Model", path.to_str().unwrap(), 0.1);
+
+        let reports = provider.get_reports("", 55.75, 37.62, 1).unwrap().forecasts;
+        assert_eq!(reports[0].report.temperature, 2.0);
+
+        // The modified file is read again and becomes the most recent one.
+        write(&path.join("a.ndjson"), &record(3.0), 3_000);
+        let reports = provider.get_reports("", 55.75, 37.62, 1).unwrap().forecasts;
+        assert_eq!(reports[0].report.temperature, 3.0);
+
+        fs::remove_file(path.join("a.ndjson")).unwrap();
+        let reports = provider.get_reports("", 55.75, 37.62, 1).unwrap().forecasts;
+        assert_eq!(reports[0].report.temperature, 2.0);
+    }
+
+    #[test]
+    fn schema_violations() {
+        let path = directory("schema").join("forecast.csv");
+        let provider = File::new("Model", path.to_str().unwrap(), 0.1);
+
+        let error = provider.get_reports("", 0.0, 0.0, 1).unwrap_err();
+        assert!(matches!(error, ProviderError::Io(_)));
+
+        write(
+            &path,
+            "lat,lon,date,temperature,temp\n0,0,2019-03-11,1,2\n",
+            1_000,
+        );
+        let error = provider.get_reports("", 0.0, 0.0, 1).unwrap_err();
+        assert!(matches!(error, ProviderError::Parse(_)));
+
+        let content = "lat,lon,date,temperature,condition\n0,0,2019-03-11,1,volcano\n";
+        write(&path, content, 2_000);
+        let error = provider.get_reports("", 0.0, 0.0, 1).unwrap_err();
+        assert!(error
+            .to_string()
+            .ends_with("Unknown condition code \"volcano\""));
+
+        let path = path.with_extension("ndjson");
+        let content = r#"{"lat": 0, "lon": 0, "date": "2019-03-11", "temperature": 1, "temp": 2}"#;
+        write(&path, content, 1_000);
+        let error = File::new("Model", path.to_str().unwrap(), 0.1)
+            .get_reports("", 0.0, 0.0, 1)
+            .unwrap_err();
+        assert!(error.to_string().ends_with("Unknown field \"temp\""));
+    }
+}
@@ -0,0 +1,255 @@
+use chrono::prelude::*;
+use serde_json::Value;
+
+use super::super::config::{self, Field};
+use super::super::http::HttpClient;
+use super::super::provider::{Provider, ProviderError};
+use super::super::reporter::{DailyForecast, ProviderForecast, Report};
+
+/// Provider of an upstream described in the config: see `config::Generic`.
+pub struct Generic {
+    config: config::Generic,
+    client: HttpClient,
+}
+
+impl Generic {
+    pub fn new(generic_config: &config::Generic, client: HttpClient) -> Self {
+        Self {
+            config: generic_config.clone(),
+            client,
+        }
+    }
+
+    /// Makes a call to the upstream and returns the parsed JSON response.
+    fn get_forecast(
+        &self,
+        api_key: &str,
+        lat: f32,
+        lon: f32,
+        days: usize,
+    ) -> Result<Value, ProviderError> {
+        let render = |template: &str| {
+            template
+                .replace("{lat}", &lat.to_string())
+                .replace("{lon}", &lon.to_string())
+                .replace("{days}", &days.to_string())
+                .replace("{api_key}", api_key)
+        };
+
+        let url = render(&self.config.url);
+        let headers = self
+            .config
+            .headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), render(value)))
+            .collect::<Vec<_>>();
+
+        let mut response = self.client.send(|client| {
+            headers
+                .iter()
+                .fold(client.get(url.as_str()), |request, (name, value)| {
+                    request.header(*name, value.as_str())
+                })
+        })?;
+
+        match response.status() {
+            reqwest::StatusCode::OK => Ok(response.json()?),
+            status => Err(ProviderError::unexpected_status(status)),
+        }
+    }
+
+    /// Builds reports from the array of days found in the response.
+    fn reports(&self, response: &Value) -> Result<Vec<DailyForecast>, ProviderError> {
+        response
+            .pointer(&self.config.days)
+            .and_then(Value::as_array)
+            .ok_or_else(|| {
+                ProviderError::Parse(format!("No array of days at \"{}\"", self.config.days))
+            })?
+            .iter()
+            .map(|day| self.report(day))
+            .collect()
+    }
+
+    fn report(&self, day: &Value) -> Result<DailyForecast, ProviderError> {
+        let mapping = &self.config.mapping;
+        let missing =
+            |field: &Field| ProviderError::Parse(format!("No value at \"{}\"", field.pointer));
+
+        let date = day
+            .pointer(&mapping.date.pointer)
+            .and_then(|value| date(value, &mapping.date_format))
+            .ok_or_else(|| missing(&mapping.date))?;
+        let temperature =
+            number(day, &mapping.temperature).ok_or_else(|| missing(&mapping.temperature))?;
+        let optional = |field: &Option<Field>| field.as_ref().and_then(|f| number(day, f));
+
+        let report = Report {
+            temperature_min: optional(&mapping.temperature_min),
+            temperature_max: optional(&mapping.temperature_max),
+            humidity: optional(&mapping.humidity),
+            pressure: optional(&mapping.pressure),
+            wind_speed: optional(&mapping.wind_speed),
+            wind_direction: optional(&mapping.wind_direction),
+            wind_gust: optional(&mapping.wind_gust),
+            precipitation: optional(&mapping.precipitation),
+            precipitation_probability: optional(&mapping.precipitation_probability),
+            cloud_cover: optional(&mapping.cloud_cover),
+            condition: mapping
+                .condition
+                .as_ref()
+                .and_then(|field| day.pointer(&field.pointer))
+                .and_then(|value| mapping.conditions.get(&key(value)))
+                .copied(),
+            ..Report::new(temperature)
+        };
+
+        Ok(DailyForecast::new(date, report))
+    }
+}
+
+impl Provider for Generic {
+    fn name(&self) -> String {
+        self.config.title.clone()
+    }
+
+    /// Gets forecasts from the upstream and maps them to reports as configured.
+    fn get_reports(
+        &self,
+        api_key: &str,
+        lat: f32,
+        lon: f32,
+        days: usize,
+    ) -> Result<ProviderForecast, ProviderError> {
+        let response = self.get_forecast(api_key, lat, lon, days)?;
+        let forecasts = self.reports(&response)?.into_iter().take(days).collect();
+        Ok(ProviderForecast::new(forecasts))
+    }
+}
+
+/// Reads a number or a numeric string at the field's pointer and converts it.
+fn number(day: &Value, field: &Field) -> Option<f32> {
+    let value = match day.pointer(&field.pointer)? {
+        Value::Number(number) => number.as_f64()? as f32,
+        Value::String(string) => string.trim().parse().ok()?,
+        _ => return None,
+    };
+
+    Some(value * field.scale + field.offset)
+}
+
+/// Parses a date string in the given format or a Unix timestamp.
+fn date(value: &Value, format: &str) -> Option<NaiveDate> {
+    match value {
+        Value::String(string) => NaiveDate::parse_from_str(string, format).ok(),
+        Value::Number(number) => Some(Utc.timestamp(number.as_i64()?, 0).date().naive_utc()),
+        _ => None,
+    }
+}
+
+/// Key of a condition value in `conditions`: strings as they are, other values as JSON.
+fn key(value: &Value) -> String {
+    match value {
+        Value::String(string) => string.clone(),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::reporter::Condition;
+
+    fn provider() -> Generic {
+        let provider_config: config::ProviderConfig = serde_json::from_value(json!({
+            "name": "Generic",
+            "title": "Acme",
+            "url": "https://acme.test/forecast?lat={lat}&lon={lon}",
+            "days": "/data/days",
+            "mapping": {
+                "date": "/time",
+                "temperature": "/temp/avg",
+                "temperature_max": { "pointer": "/temp/max_f", "scale": 0.5, "offset": -16.0 },
+                "cloud_cover": { "pointer": "/clouds", "scale": 100.0 },
+                "precipitation": "/rain",
+                "condition": "/code",
+                "conditions": { "10": "clear", "20": "light_rain" }
+            }
+        }))
+        .unwrap();
+
+        match provider_config.provider {
+            config::Provider::Generic(generic_config) => {
+                Generic::new(&generic_config, HttpClient::new(&provider_config.http))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn map_reports() {
+        let response = json!({
+            "data": {
+                "days": [
+                    {
+                        "time": "2019-03-11",
+                        "temp": { "avg": -10.5, "max_f": 14.0 },
+                        "clouds": 0.75,
+                        "rain": "1.25",
+                        "code": 20
+                    },
+                    { "time": 1552435200, "temp": { "avg": 3.0 }, "code": 99 }
+                ]
+            }
+        });
+
+        let provider = provider();
+        assert_eq!(provider.name(), "Acme");
+
+        let reports = provider.reports(&response).unwrap();
+        assert_eq!(reports.len(), 2);
+
+        let first = &reports[0];
+        assert_eq!(first.date, NaiveDate::from_ymd(2019, 3, 11));
+        assert_eq!(first.report.temperature, -10.5);
+        assert_eq!(first.report.temperature_max, Some(-9.0));
+        assert_eq!(first.report.cloud_cover, Some(75.0));
+        assert_eq!(first.report.precipitation, Some(1.25));
+        assert_eq!(first.report.condition, Some(Condition::LightRain));
+
+        let second = &reports[1];
+        assert_eq!(second.date, NaiveDate::from_ymd(2019, 3, 13));
+        assert_eq!(second.report.temperature_max, None);
+        assert_eq!(second.report.condition, None);
+    }
+
+    #[test]
+    fn unknown_condition_code() {
+        let result = serde_json::from_value::<config::Generic>(json!({
+            "title": "Acme",
+            "url": "https://acme.test/forecast",
+            "mapping": {
+                "date": "/time",
+                "temperature": "/temp",
+                "conditions": { "1": "volcano" }
+            }
+        }));
+
+        let error = result.err().unwrap().to_string();
+        assert!(error.contains("unknown condition code \"volcano\""));
+    }
+
+    #[test]
+    fn missing_values() {
+        let provider = provider();
+
+        let error = provider.reports(&json!({ "days": [] })).unwrap_err();
+        assert_eq!(error.to_string(), "No array of days at \"/data/days\"");
+
+        let response = json!({ "data": { "days": [{ "time": "2019-03-11" }] } });
+        let error = provider.reports(&response).unwrap_err();
+        assert_eq!(error.to_string(), "No value at \"/temp/avg\"");
+    }
+}
@@ -0,0 +1,238 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use super::super::config::{self, RotationPolicy};
+use super::quota::{Quota, QuotaStore};
+
+/// API keys of a provider rotated between calls.
+///
+/// Keys rejected by the upstream are quarantined: they are skipped for `quarantine`
+/// and then taken back into rotation. Each key has its own quota.
+/// Providers without keys get a single empty key so that their quota still applies.
+pub struct KeyRing {
+    keys: Vec<Key>,
+    /// Whether the provider has no keys configured and uses the empty one.
+    keyless: bool,
+    policy: RotationPolicy,
+    quarantine: Duration,
+    state: Mutex<State>,
+}
+
+struct Key {
+    value: String,
+    quota: Option<Arc<Quota>>,
+}
+
+struct State {
+    /// Index of the key to start the next round-robin pick from.
+    next: usize,
+    uses: Vec<u64>,
+    quarantined_until: Vec<Option<Instant>>,
+}
+
+// Number of the last key characters shown by the admin endpoint.
+const VISIBLE_KEY_CHARS: usize = 4;
+
+/// Key state for the admin endpoint. Only the end of the key is shown.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct KeyState {
+    pub key: String,
+    pub uses: u64,
+    /// Time left until the key is taken back into rotation, ms.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quarantined_for_ms: Option<u64>,
+}
+
+impl KeyRing {
+    pub fn new(
+        provider: &str,
+        provider_config: &config::ProviderConfig,
+        quota_store: &Arc<QuotaStore>,
+    ) -> Self {
+        let mut values = provider_config.provider.api_keys().to_vec();
+        let keyless = values.is_empty();
+        if keyless {
+            values.push(String::new());
+        }
+
+        let keys = values
+            .into_iter()
+            .map(|value| Key {
+                quota: provider_config.quota.as_ref().map(|quota_config| {
+                    Arc::new(Quota::new(
+                        provider,
+                        &value,
+                        quota_config,
+                        Arc::clone(quota_store),
+                    ))
+                }),
+                value,
+            })
+            .collect::<Vec<_>>();
+
+        let count = keys.len();
+        let rotation = &provider_config.key_rotation;
+
+        Self {
+            keys,
+            keyless,
+            policy: rotation.policy,
+            quarantine: Duration::from_millis(rotation.quarantine_ms),
+            state: Mutex::new(State {
+                next: 0,
+                uses: vec![0; count],
+                quarantined_until: vec![None; count],
+            }),
+        }
+    }
+
+    /// Picks a key for the next call according to the rotation policy.
+    /// Returns `None` when all keys are either quarantined or out of quota.
+    pub fn acquire(&self) -> Option<usize> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+
+        for until in state.quarantined_until.iter_mut() {
+            if until.is_some_and(|until| until <= now) {
+                *until = None;
+            }
+        }
+
+        let count = self.keys.len();
+        let mut candidates = (0..count)
+            .map(|offset| (state.next + offset) % count)
+            .filter(|&index| state.quarantined_until[index].is_none())
+            .collect::<Vec<_>>();
+
+        if self.policy == RotationPolicy::LeastUsed {
+            candidates.sort_by_key(|&index| (state.uses[index], index));
+        }
+
+        let index = candidates.into_iter().find(|&index| {
+            self.keys[index]
+                .quota
+                .as_ref()
+                .is_none_or(|quota| quota.try_acquire())
+        })?;
+
+        state.uses[index] += 1;
+        state.next = (index + 1) % count;
+        Some(index)
+    }
+
+    /// Tells whether the provider has no keys configured.
+    pub fn is_keyless(&self) -> bool {
+        self.keyless
+    }
+
+    pub fn key(&self, index: usize) -> &str {
+        &self.keys[index].value
+    }
+
+    /// Returns a check taking a token of the key's quota for one more request.
+    /// It always succeeds for keys without a quota.
+    pub fn take_token(&self, index: usize) -> impl Fn() -> bool + 'static {
+        let quota = self.keys[index].quota.clone();
+        move || quota.as_ref().is_none_or(|quota| quota.try_acquire())
+    }
+
+    /// Takes the key out of rotation for the quarantine period.
+    pub fn quarantine(&self, index: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.quarantined_until[index] = Some(Instant::now() + self.quarantine);
+    }
+
+    pub fn states(&self) -> Vec<KeyState> {
+        let state = self.state.lock().unwrap();
+        let now = Instant::now();
+
+        self.keys
+            .iter()
+            .enumerate()
+            .map(|(index, key)| KeyState {
+                key: masked_key(&key.value),
+                uses: state.uses[index],
+                quarantined_for_ms: state.quarantined_until[index]
+                    .filter(|&until| until > now)
+                    .map(|until| until.duration_since(now).as_millis() as u64),
+            })
+            .collect()
+    }
+}
+
+/// Hides all but the last `VISIBLE_KEY_CHARS` characters of the key.
+/// Keys that short are hidden completely.
+fn masked_key(key: &str) -> String {
+    let chars = key.chars().collect::<Vec<_>>();
+
+    if chars.len() <= VISIBLE_KEY_CHARS {
+        return String::from("****");
+    }
+
+    let visible = chars[chars.len() - VISIBLE_KEY_CHARS..]
+        .iter()
+        .collect::<String>();
+    format!("****{}", visible)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    fn key_ring(policy: &str) -> KeyRing {
+        let provider_config: config::ProviderConfig = serde_json::from_value(serde_json::json!({
+            "name": "Yandex",
+            "api_keys": ["first", "second", "third"],
+            "key_rotation": { "policy": policy },
+        }))
+        .unwrap();
+
+        let path = env::temp_dir().join(format!("weather_report_keys_{}.json", process::id()));
+        let quota_store = Arc::new(QuotaStore::load(&path.to_string_lossy()));
+
+        KeyRing::new("Yandex", &provider_config, &quota_store)
+    }
+
+    #[test]
+    fn round_robin() {
+        let keys = key_ring("round_robin");
+        let picks = (0..4).map(|_| keys.acquire().unwrap()).collect::<Vec<_>>();
+        assert_eq!(picks, vec![0, 1, 2, 0]);
+        assert_eq!(keys.key(1), "second");
+
+        keys.quarantine(1);
+        let picks = (0..3).map(|_| keys.acquire().unwrap()).collect::<Vec<_>>();
+        assert_eq!(picks, vec![2, 0, 2]);
+
+        keys.quarantine(0);
+        keys.quarantine(2);
+        assert_eq!(keys.acquire(), None);
+        assert!(keys.states()[1].quarantined_for_ms.is_some());
+        assert_eq!(keys.states()[1].key, "****cond");
+    }
+
+    #[test]
+    fn least_used() {
+        let keys = key_ring("least_used");
+        assert_eq!(keys.acquire(), Some(0));
+        assert_eq!(keys.acquire(), Some(1));
+
+        // The quarantined key is used less but skipped.
+        keys.quarantine(2);
+        assert_eq!(keys.acquire(), Some(0));
+        assert_eq!(keys.acquire(), Some(1));
+    }
+
+    #[test]
+    fn masked_keys() {
+        assert_eq!(masked_key("a1b2c3d4"), "****c3d4");
+        assert_eq!(masked_key("ключ-ключ"), "****ключ");
+        assert_eq!(masked_key("a1b2"), "****");
+        assert_eq!(masked_key(""), "****");
+    }
+}
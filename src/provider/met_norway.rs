@@ -0,0 +1,369 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+use chrono::Duration;
+use reqwest::header::{EXPIRES, IF_MODIFIED_SINCE, LAST_MODIFIED, USER_AGENT};
+use reqwest::{Response, StatusCode};
+use serde::Deserialize;
+
+use super::super::http::HttpClient;
+use super::super::provider::{Provider, ProviderError};
+use super::super::reporter::{Condition, DailyForecast, ProviderForecast, Report};
+use super::daily::{self, Slot};
+
+// Expired forecasts are kept for revalidation with `If-Modified-Since` for this long.
+const CACHE_RETENTION_HOURS: i64 = 24;
+
+/// MET Norway Locationforecast provider.
+///
+/// MET's terms require an identifying `User-Agent` and caching: a forecast is reused until
+/// its `Expires` time and then revalidated with `If-Modified-Since`.
+pub struct MetNorway {
+    user_agent: String,
+    base_url: String,
+    client: HttpClient,
+    cache: Mutex<HashMap<String, CacheEntry>>,
+}
+
+#[derive(Clone)]
+struct CacheEntry {
+    forecast: Arc<ForecastResponse>,
+    expires: DateTime<Utc>,
+    last_modified: Option<String>,
+}
+
+impl MetNorway {
+    pub fn new(user_agent: &str, base_url: &str, client: HttpClient) -> Self {
+        Self {
+            user_agent: String::from(user_agent),
+            base_url: String::from(base_url.trim_end_matches('/')),
+            client,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the cached forecast while it's fresh. Otherwise makes a call to MET
+    /// Locationforecast API and caches the response.
+    fn get_forecast(&self, lat: f32, lon: f32) -> Result<Arc<ForecastResponse>, ProviderError> {
+        // MET asks to use at most 4 decimals, more only spoil caching.
+        let url = format!(
+            "{}/weatherapi/locationforecast/2.0/compact?lat={:.4}&lon={:.4}",
+            self.base_url, lat, lon
+        );
+
+        let cached = self.cache.lock().unwrap().get(&url).cloned();
+
+        if let Some(entry) = &cached {
+            if Utc::now() < entry.expires {
+                return Ok(Arc::clone(&entry.forecast));
+            }
+        }
+
+        let last_modified = cached.as_ref().and_then(|e| e.last_modified.clone());
+
+        let mut response = self.client.send(|client| {
+            let request = client
+                .get(url.as_str())
+                .header(USER_AGENT, self.user_agent.as_str());
+
+            match &last_modified {
+                Some(last_modified) => request.header(IF_MODIFIED_SINCE, last_modified.as_str()),
+                None => request,
+            }
+        })?;
+
+        let forecast = match (response.status(), cached) {
+            (StatusCode::OK, _) => Arc::new(response.json()?),
+            (StatusCode::NOT_MODIFIED, Some(entry)) => entry.forecast,
+            (status, _) => return Err(ProviderError::unexpected_status(status)),
+        };
+
+        let now = Utc::now();
+        let entry = CacheEntry {
+            forecast: Arc::clone(&forecast),
+            expires: header(&response, EXPIRES)
+                .and_then(|value| DateTime::parse_from_rfc2822(&value).ok())
+                .map_or(now, |expires| expires.with_timezone(&Utc)),
+            last_modified: header(&response, LAST_MODIFIED).or(last_modified),
+        };
+
+        let mut cache = self.cache.lock().unwrap();
+        cache.retain(|_, entry| entry.expires + Duration::hours(CACHE_RETENTION_HOURS) > now);
+        cache.insert(url, entry);
+
+        Ok(forecast)
+    }
+}
+
+impl Provider for MetNorway {
+    fn name(&self) -> String {
+        String::from("MetNorway")
+    }
+
+    /// Gets hourly forecasts from MET Norway and folds them into daily reports.
+    fn get_reports(
+        &self,
+        _api_key: &str,
+        lat: f32,
+        lon: f32,
+        days: usize,
+    ) -> Result<ProviderForecast, ProviderError> {
+        let response = self.get_forecast(lat, lon)?;
+        let forecasts = fold_daily(&response, lon).into_iter().take(days).collect();
+        Ok(ProviderForecast::new(forecasts))
+    }
+}
+
+fn header(response: &Response, name: reqwest::header::HeaderName) -> Option<String> {
+    response
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from)
+}
+
+/// Converts MET timeseries to slots and folds them into days. MET doesn't tell the location's
+/// time zone so it's approximated by the longitude.
+fn fold_daily(response: &ForecastResponse, lon: f32) -> Vec<DailyForecast> {
+    let offset = daily::estimated_utc_offset(lon);
+    let slots = response
+        .properties
+        .timeseries
+        .iter()
+        .map(Timestep::slot)
+        .collect();
+
+    daily::fold_daily(slots, offset)
+}
+
+impl Timestep {
+    /// Hourly steps come with the next hour summary, further ones only with 6 hours summaries.
+    fn slot(&self) -> Slot {
+        let details = &self.data.instant.details;
+
+        let (hours, period) = match (&self.data.next_1_hours, &self.data.next_6_hours) {
+            (Some(period), _) => (1, Some(period)),
+            (None, Some(period)) => (6, Some(period)),
+            (None, None) => (1, None),
+        };
+
+        let report = Report {
+            humidity: details.relative_humidity,
+            pressure: details.air_pressure_at_sea_level,
+            wind_speed: details.wind_speed,
+            wind_direction: details.wind_from_direction,
+            precipitation: period
+                .and_then(|p| p.details.as_ref())
+                .and_then(|d| d.precipitation_amount),
+            cloud_cover: details.cloud_area_fraction,
+            condition: period
+                .and_then(|p| p.summary.as_ref())
+                .and_then(|s| condition(&s.symbol_code)),
+            ..Report::new(details.air_temperature)
+        };
+
+        Slot {
+            start: self.time,
+            end: self.time + Duration::hours(hours),
+            report,
+        }
+    }
+}
+
+/*
+Structs for parsing MET Norway Locationforecast API response with serde.
+Example response (simplified):
+
+{
+    "properties": {
+        "timeseries": [
+            {
+                "time": "2019-03-11T12:00:00Z",
+                "data": {
+                    "instant": {
+                        "details": {
+                            "air_pressure_at_sea_level": 1012.4,
+                            "air_temperature": -10.2,
+                            "cloud_area_fraction": 75.0,
+                            "relative_humidity": 76.0,
+                            "wind_from_direction": 315.0,
+                            "wind_speed": 3.5
+                        }
+                    },
+                    "next_1_hours": {
+                        "summary": { "symbol_code": "lightsnow" },
+                        "details": { "precipitation_amount": 0.4 }
+                    },
+                    "next_6_hours": {
+                        "summary": { "symbol_code": "snow" },
+                        "details": { "precipitation_amount": 1.2 }
+                    }
+                }
+            }
+        ]
+    }
+}
+*/
+
+#[derive(Debug, Deserialize)]
+struct ForecastResponse {
+    properties: Properties,
+}
+
+#[derive(Debug, Deserialize)]
+struct Properties {
+    timeseries: Vec<Timestep>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Timestep {
+    time: DateTime<Utc>,
+    data: Data,
+}
+
+#[derive(Debug, Deserialize)]
+struct Data {
+    instant: Instant,
+    next_1_hours: Option<Period>,
+    next_6_hours: Option<Period>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Instant {
+    details: InstantDetails,
+}
+
+#[derive(Debug, Deserialize)]
+struct InstantDetails {
+    /// °C.
+    air_temperature: f32,
+    /// hPa.
+    air_pressure_at_sea_level: Option<f32>,
+    /// %.
+    relative_humidity: Option<f32>,
+    /// m/s.
+    wind_speed: Option<f32>,
+    /// Degrees.
+    wind_from_direction: Option<f32>,
+    /// %.
+    cloud_area_fraction: Option<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Period {
+    summary: Option<Summary>,
+    details: Option<PeriodDetails>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Summary {
+    /// Code like "lightrainshowers_day".
+    symbol_code: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PeriodDetails {
+    /// mm.
+    precipitation_amount: Option<f32>,
+}
+
+/// Maps a MET symbol code to the shared condition. The time of day suffix like "_night"
+/// is ignored and codes "…andthunder" are mapped to thunderstorm.
+/// Returns `None` for unknown codes.
+fn condition(symbol_code: &str) -> Option<Condition> {
+    let symbol = symbol_code.split('_').next().unwrap_or(symbol_code);
+
+    if symbol.ends_with("andthunder") {
+        return Some(Condition::Thunderstorm);
+    }
+
+    let condition = match symbol {
+        "clearsky" => Condition::Clear,
+        "fair" | "partlycloudy" => Condition::PartlyCloudy,
+        "cloudy" => Condition::Cloudy,
+        "fog" => Condition::Fog,
+        "lightrain" => Condition::LightRain,
+        "rain" => Condition::Rain,
+        "heavyrain" => Condition::HeavyRain,
+        "lightrainshowers" | "rainshowers" | "heavyrainshowers" => Condition::Showers,
+        "lightsleet" | "sleet" | "heavysleet" | "lightsleetshowers" | "sleetshowers"
+        | "heavysleetshowers" => Condition::Sleet,
+        "lightsnow" => Condition::LightSnow,
+        "snow" => Condition::Snow,
+        "heavysnow" => Condition::HeavySnow,
+        "lightsnowshowers" | "snowshowers" | "heavysnowshowers" => Condition::SnowShowers,
+        _ => return None,
+    };
+
+    Some(condition)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timestep(time: &str, temperature: f32, next_hours: &str, precipitation: f32) -> String {
+        format!(
+            r#"{{
+                "time": "{}",
+                "data": {{
+                    "instant": {{ "details": {{ "air_temperature": {}, "wind_speed": 2.0 }} }},
+                    "{}": {{
+                        "summary": {{ "symbol_code": "lightsnow" }},
+                        "details": {{ "precipitation_amount": {} }}
+                    }}
+                }}
+            }}"#,
+            time, temperature, next_hours, precipitation
+        )
+    }
+
+    #[test]
+    fn fold_daily_in_local_time_zone() {
+        // Longitude 37.5 gives UTC+3, so 21:00 UTC is the next local day.
+        let timeseries = [
+            timestep("2019-03-11T20:00:00Z", -2.0, "next_1_hours", 0.25),
+            timestep("2019-03-11T21:00:00Z", -6.0, "next_1_hours", 0.5),
+            timestep("2019-03-11T22:00:00Z", -4.0, "next_6_hours", 1.0),
+            timestep("2019-03-12T04:00:00Z", -1.0, "next_6_hours", 0.0),
+        ];
+        let json = format!(
+            r#"{{ "properties": {{ "timeseries": [{}] }} }}"#,
+            timeseries.join(",")
+        );
+        let response: ForecastResponse = serde_json::from_str(&json).unwrap();
+
+        let forecasts = fold_daily(&response, 37.5);
+        assert_eq!(forecasts.len(), 2);
+
+        let first = &forecasts[0];
+        assert_eq!(first.date, NaiveDate::from_ymd(2019, 3, 11));
+        assert_eq!(first.report.temperature, -2.0);
+        assert_eq!(first.report.precipitation, Some(0.25));
+
+        let second = &forecasts[1];
+        assert_eq!(second.date, NaiveDate::from_ymd(2019, 3, 12));
+        assert_eq!(second.report.temperature_min, Some(-6.0));
+        assert_eq!(second.report.temperature_max, Some(-1.0));
+        assert_eq!(second.report.wind_speed, Some(2.0));
+        assert_eq!(second.report.precipitation, Some(1.5));
+        assert_eq!(second.report.condition, Some(Condition::LightSnow));
+
+        // The last slot covers 6 hours.
+        let (_, end) = second.period.unwrap();
+        assert_eq!(end.to_rfc3339(), "2019-03-12T10:00:00+00:00");
+    }
+
+    #[test]
+    fn condition_mapping() {
+        assert_eq!(condition("clearsky_night"), Some(Condition::Clear));
+        assert_eq!(condition("lightrainshowers_day"), Some(Condition::Showers));
+        assert_eq!(
+            condition("heavysnowandthunder"),
+            Some(Condition::Thunderstorm)
+        );
+        assert_eq!(condition("sleet"), Some(Condition::Sleet));
+        assert_eq!(condition("volcano"), None);
+    }
+}
@@ -1,33 +1,254 @@
+mod circuit_breaker;
+mod command;
+mod daily;
+mod error;
+mod file;
+mod generic;
+mod key_ring;
+mod met_norway;
+mod nws;
+mod open_meteo;
 mod open_weather_map;
+mod quota;
+mod weather_api;
 mod yandex;
 
-use std::cell::RefCell;
-use std::error::Error;
-use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
 
 use super::config;
-use super::reporter::Report;
+use super::http::{self, HttpClient};
+use super::reporter::ProviderForecast;
+use command::Command;
+use file::File;
+use generic::Generic;
+use met_norway::MetNorway;
+use nws::Nws;
+use open_meteo::OpenMeteo;
 use open_weather_map::OpenWeatherMap;
+use weather_api::WeatherApi;
 use yandex::Yandex;
 
-fn build_provider(provider_config: &config::Provider) -> Box<dyn Provider> {
-    match provider_config {
-        config::Provider::Yandex { api_key } => Box::new(Yandex::new(api_key)),
-        config::Provider::OpenWeatherMap { api_key } => Box::new(OpenWeatherMap::new(api_key)),
+pub use circuit_breaker::CircuitBreaker;
+pub use daily::estimated_utc_offset;
+pub use error::ProviderError;
+pub use key_ring::KeyRing;
+pub use quota::QuotaStore;
+
+fn build_provider(provider_config: &config::ProviderConfig) -> Box<dyn Provider> {
+    let client = HttpClient::new(&provider_config.http);
+
+    match &provider_config.provider {
+        config::Provider::Yandex { base_url, .. } => Box::new(Yandex::new(base_url, client)),
+        config::Provider::OpenWeatherMap { base_url, .. } => {
+            Box::new(OpenWeatherMap::new(base_url, client))
+        }
+        config::Provider::WeatherApi { base_url, .. } => {
+            Box::new(WeatherApi::new(base_url, client))
+        }
+        config::Provider::OpenMeteo { base_url, .. } => Box::new(OpenMeteo::new(base_url, client)),
+        config::Provider::MetNorway {
+            user_agent,
+            base_url,
+        } => Box::new(MetNorway::new(user_agent, base_url, client)),
+        config::Provider::Nws {
+            user_agent,
+            base_url,
+        } => Box::new(Nws::new(user_agent, base_url, client)),
+        config::Provider::Generic(generic_config) => Box::new(Generic::new(generic_config, client)),
+        config::Provider::Command {
+            title,
+            command,
+            args,
+            timeout_ms,
+        } => Box::new(Command::new(
+            title,
+            command,
+            args,
+            Duration::from_millis(*timeout_ms),
+        )),
+        config::Provider::File {
+            title,
+            path,
+            max_distance,
+        } => Box::new(File::new(title, path, *max_distance)),
+    }
+}
+
+/// A provider along with its common settings from the config.
+pub struct ProviderEntry {
+    pub provider: Box<dyn Provider>,
+    pub weight: f32,
+    pub circuit_breaker: CircuitBreaker,
+    pub keys: KeyRing,
+}
+
+impl ProviderEntry {
+    fn new(provider_config: &config::ProviderConfig, quota_store: &Arc<QuotaStore>) -> Self {
+        let provider = build_provider(provider_config);
+        let keys = KeyRing::new(&provider.name(), provider_config, quota_store);
+
+        Self {
+            provider,
+            weight: provider_config.weight,
+            circuit_breaker: CircuitBreaker::new(&provider_config.circuit_breaker),
+            keys,
+        }
+    }
+
+    /// Gets reports with the key taken from `acquire` of the key ring. When the key
+    /// is rejected, quarantines it and retries with the next available key.
+    /// Providers without keys fail with `ProviderError::Http` instead.
+    pub fn get_reports(
+        &self,
+        key: usize,
+        lat: f32,
+        lon: f32,
+        days: usize,
+    ) -> Result<ProviderForecast, ProviderError> {
+        let mut key = key;
+
+        loop {
+            // The first request is paid for by `acquire`, the rest take quota tokens.
+            let result = http::with_quota(self.keys.take_token(key), || {
+                self.provider
+                    .get_reports(self.keys.key(key), lat, lon, days)
+            });
+
+            match result {
+                // There is no key to quarantine, so the rejection is an ordinary failure.
+                Err(ProviderError::KeyRejected(message)) if self.keys.is_keyless() => {
+                    return Err(ProviderError::Http(message));
+                }
+                Err(ProviderError::KeyRejected(_)) => {
+                    self.keys.quarantine(key);
+
+                    match self.keys.acquire() {
+                        Some(next) => key = next,
+                        None => return result,
+                    }
+                }
+                _ => return result,
+            }
+        }
     }
 }
 
-thread_local! {
-    // Weather forecast providers reference.
-    pub static PROVIDERS: Rc<RefCell<Vec<Box<dyn Provider>>>> = {
+lazy_static! {
+    // Weather forecast providers shared by all threads so that their circuit breakers
+    // and quotas see every call.
+    pub static ref PROVIDERS: Vec<ProviderEntry> = {
         config::CONFIG.with(|config| {
-            let providers = config.borrow().providers.iter().map(|p| build_provider(p)).collect();
-            Rc::new(RefCell::new(providers))
+            let config = config.borrow();
+            let quota_store = Arc::new(QuotaStore::load(&config.reporter.quota_state_path));
+            QuotaStore::start_saving(&quota_store);
+
+            config
+                .providers
+                .iter()
+                .map(|provider_config| ProviderEntry::new(provider_config, &quota_store))
+                .collect()
         })
     };
 }
 
-pub trait Provider {
+/// Providers are called concurrently from separate threads.
+pub trait Provider: Send + Sync {
     fn name(&self) -> String;
-    fn get_reports(&self, lat: f32, lon: f32, days: usize) -> Result<Vec<Report>, Box<dyn Error>>;
+
+    /// Returns forecasts for `days` days starting from today in the location's time zone.
+    /// `api_key` is one of the provider's keys picked for the call; it's empty for providers
+    /// without keys.
+    /// Forecasts are tagged with their dates so they may come in any order and the provider
+    /// is free to skip days it has no data for. The location's UTC offset should be given
+    /// when the upstream tells it since the reporter counts days from the location's today.
+    fn get_reports(
+        &self,
+        api_key: &str,
+        lat: f32,
+        lon: f32,
+        days: usize,
+    ) -> Result<ProviderForecast, ProviderError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    /// Provider rejecting every key with HTTP 403.
+    struct Rejecting {
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl Provider for Rejecting {
+        fn name(&self) -> String {
+            String::from("Rejecting")
+        }
+
+        fn get_reports(
+            &self,
+            _api_key: &str,
+            _lat: f32,
+            _lon: f32,
+            _days: usize,
+        ) -> Result<ProviderForecast, ProviderError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Err(ProviderError::unexpected_status(
+                reqwest::StatusCode::FORBIDDEN,
+            ))
+        }
+    }
+
+    fn entry(api_keys: &[&str], calls: &Arc<AtomicUsize>) -> ProviderEntry {
+        let provider_config: config::ProviderConfig = serde_json::from_value(serde_json::json!({
+            "name": "OpenMeteo",
+            "api_keys": api_keys,
+        }))
+        .unwrap();
+
+        let path = env::temp_dir().join(format!("weather_report_entry_{}.json", process::id()));
+        let quota_store = Arc::new(QuotaStore::load(&path.to_string_lossy()));
+
+        ProviderEntry {
+            provider: Box::new(Rejecting {
+                calls: Arc::clone(calls),
+            }),
+            weight: 1.0,
+            circuit_breaker: CircuitBreaker::new(&provider_config.circuit_breaker),
+            keys: KeyRing::new("Rejecting", &provider_config, &quota_store),
+        }
+    }
+
+    #[test]
+    fn rejected_keys_quarantined() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let entry = entry(&["first", "second"], &calls);
+
+        let key = entry.keys.acquire().unwrap();
+        let error = entry.get_reports(key, 0.0, 0.0, 1).unwrap_err();
+        assert!(matches!(error, ProviderError::KeyRejected(_)));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(entry.keys.acquire(), None);
+    }
+
+    #[test]
+    fn keyless_rejection_is_http_failure() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let entry = entry(&[], &calls);
+
+        for _ in 0..2 {
+            let key = entry.keys.acquire().unwrap();
+            let error = entry.get_reports(key, 0.0, 0.0, 1).unwrap_err();
+            assert!(matches!(error, ProviderError::Http(_)));
+        }
+
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert!(entry.keys.states()[0].quarantined_for_ms.is_none());
+    }
 }
@@ -0,0 +1,416 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use chrono::prelude::*;
+use reqwest::header::USER_AGENT;
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+use super::super::http::HttpClient;
+use super::super::provider::{Provider, ProviderError};
+use super::super::reporter::{Condition, DailyForecast, ProviderForecast, Report};
+
+// Resolved gridpoints are cached per coordinates rounded to this number of decimals (~1 km).
+const GRIDPOINT_PRECISION: i32 = 2;
+// The cache is cleared when it grows over this size.
+const GRIDPOINT_CACHE_CAPACITY: usize = 10_000;
+
+const KMH_IN_MS: f32 = 3.6;
+
+/// US National Weather Service provider.
+///
+/// NWS forecasts take two calls: the point lookup resolves coordinates to a forecast office
+/// gridpoint, then the gridpoint forecast is requested. Resolved gridpoints are cached.
+/// Locations outside NWS coverage are declined with `ProviderError::OutOfCoverage`.
+pub struct Nws {
+    user_agent: String,
+    base_url: String,
+    client: HttpClient,
+    /// Gridpoints by rounded coordinates, `None` for locations outside coverage.
+    gridpoints: Mutex<HashMap<(i64, i64), Option<Gridpoint>>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Gridpoint {
+    /// Forecast office like "TOP".
+    grid_id: String,
+    grid_x: u32,
+    grid_y: u32,
+}
+
+impl Nws {
+    pub fn new(user_agent: &str, base_url: &str, client: HttpClient) -> Self {
+        Self {
+            user_agent: String::from(user_agent),
+            base_url: String::from(base_url.trim_end_matches('/')),
+            client,
+            gridpoints: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the gridpoint of the location, `None` when it's outside NWS coverage.
+    fn get_gridpoint(&self, lat: f32, lon: f32) -> Result<Option<Gridpoint>, ProviderError> {
+        let scale = 10f32.powi(GRIDPOINT_PRECISION);
+        let key = ((lat * scale).round() as i64, (lon * scale).round() as i64);
+
+        if let Some(gridpoint) = self.gridpoints.lock().unwrap().get(&key) {
+            return Ok(gridpoint.clone());
+        }
+
+        let url = format!(
+            "{}/points/{:.*},{:.*}",
+            self.base_url,
+            GRIDPOINT_PRECISION as usize,
+            key.0 as f32 / scale,
+            GRIDPOINT_PRECISION as usize,
+            key.1 as f32 / scale
+        );
+
+        let mut response = self.client.send(|client| {
+            client
+                .get(url.as_str())
+                .header(USER_AGENT, self.user_agent.as_str())
+        })?;
+
+        let gridpoint = match response.status() {
+            StatusCode::OK => Some(response.json::<PointResponse>()?.properties),
+            StatusCode::NOT_FOUND => None,
+            status => return Err(ProviderError::unexpected_status(status)),
+        };
+
+        let mut gridpoints = self.gridpoints.lock().unwrap();
+        if gridpoints.len() >= GRIDPOINT_CACHE_CAPACITY {
+            gridpoints.clear();
+        }
+        gridpoints.insert(key, gridpoint.clone());
+
+        Ok(gridpoint)
+    }
+
+    /// Makes a call to NWS gridpoint forecast API and returns the response.
+    fn get_forecast(&self, gridpoint: &Gridpoint) -> Result<ForecastResponse, ProviderError> {
+        let url = format!(
+            "{}/gridpoints/{}/{},{}/forecast?units=si",
+            self.base_url, gridpoint.grid_id, gridpoint.grid_x, gridpoint.grid_y
+        );
+
+        let mut response = self.client.send(|client| {
+            client
+                .get(url.as_str())
+                .header(USER_AGENT, self.user_agent.as_str())
+        })?;
+
+        match response.status() {
+            StatusCode::OK => Ok(response.json()?),
+            status => Err(ProviderError::unexpected_status(status)),
+        }
+    }
+}
+
+impl Provider for Nws {
+    fn name(&self) -> String {
+        String::from("Nws")
+    }
+
+    /// Gets day and night forecast periods from NWS and combines them into daily reports.
+    fn get_reports(
+        &self,
+        _api_key: &str,
+        lat: f32,
+        lon: f32,
+        days: usize,
+    ) -> Result<ProviderForecast, ProviderError> {
+        let gridpoint = self
+            .get_gridpoint(lat, lon)?
+            .ok_or(ProviderError::OutOfCoverage)?;
+
+        let periods = self.get_forecast(&gridpoint)?.properties.periods;
+
+        Ok(ProviderForecast {
+            forecasts: fold_daily(&periods).into_iter().take(days).collect(),
+            utc_offset: periods.first().map(|period| *period.start_time.offset()),
+        })
+    }
+}
+
+/// Combines each day's daytime period with the night that follows it. The reported
+/// temperature is the daytime one falling back to the night one (e.g. for "Tonight").
+fn fold_daily(periods: &[Period]) -> Vec<DailyForecast> {
+    let mut days: BTreeMap<NaiveDate, (Option<&Period>, Option<&Period>)> = BTreeMap::new();
+
+    for period in periods {
+        let day = days
+            .entry(period.start_time.date().naive_local())
+            .or_default();
+
+        if period.is_daytime {
+            day.0 = Some(period);
+        } else {
+            day.1 = Some(period);
+        }
+    }
+
+    days.into_iter()
+        .filter_map(|(date, (daytime, night))| {
+            let main = daytime.or(night)?;
+            let precipitation_probability = daytime
+                .iter()
+                .chain(night.iter())
+                .filter_map(|p| p.probability_of_precipitation.as_ref()?.value)
+                .fold(None, |max: Option<f32>, x| {
+                    Some(max.map_or(x, |max| max.max(x)))
+                });
+
+            let report = Report {
+                temperature_min: night.map(|p| p.temperature),
+                temperature_max: daytime.map(|p| p.temperature),
+                humidity: main.relative_humidity.as_ref().and_then(|h| h.value),
+                wind_speed: main.wind_speed.as_ref().and_then(|s| wind_speed(s)),
+                wind_direction: main.wind_direction.as_ref().and_then(|d| wind_direction(d)),
+                precipitation_probability,
+                condition: condition(&main.short_forecast),
+                ..Report::new(main.temperature)
+            };
+
+            let start = main.start_time.with_timezone(&Utc);
+            let end = night.unwrap_or(main).end_time.with_timezone(&Utc);
+
+            Some(DailyForecast {
+                date,
+                period: Some((start, end)),
+                report,
+            })
+        })
+        .collect()
+}
+
+/*
+Structs for parsing NWS API responses with serde.
+Example point response (simplified):
+
+{
+    "properties": { "gridId": "TOP", "gridX": 31, "gridY": 80 }
+}
+
+Example gridpoint forecast response with `units=si` (simplified):
+
+{
+    "properties": {
+        "periods": [
+            {
+                "name": "Monday",
+                "startTime": "2019-03-11T06:00:00-05:00",
+                "endTime": "2019-03-11T18:00:00-05:00",
+                "isDaytime": true,
+                "temperature": 12,
+                "temperatureUnit": "C",
+                "probabilityOfPrecipitation": { "unitCode": "wmoUnit:percent", "value": 20 },
+                "relativeHumidity": { "unitCode": "wmoUnit:percent", "value": 70 },
+                "windSpeed": "10 to 15 km/h",
+                "windDirection": "NW",
+                "shortForecast": "Chance Rain Showers"
+            }
+        ]
+    }
+}
+*/
+
+#[derive(Debug, Deserialize)]
+struct PointResponse {
+    properties: Gridpoint,
+}
+
+#[derive(Debug, Deserialize)]
+struct ForecastResponse {
+    properties: Periods,
+}
+
+#[derive(Debug, Deserialize)]
+struct Periods {
+    periods: Vec<Period>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Period {
+    start_time: DateTime<FixedOffset>,
+    end_time: DateTime<FixedOffset>,
+    is_daytime: bool,
+    /// °C with `units=si`.
+    temperature: f32,
+    probability_of_precipitation: Option<Quantity>,
+    relative_humidity: Option<Quantity>,
+    /// Like "10 km/h" or "10 to 15 km/h".
+    wind_speed: Option<String>,
+    /// Compass point like "NNW".
+    wind_direction: Option<String>,
+    /// Text like "Chance Rain Showers".
+    short_forecast: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Quantity {
+    value: Option<f32>,
+}
+
+/// Parses the wind speed in km/h or mph and converts it to m/s.
+/// Ranges like "10 to 15 km/h" give the middle value.
+fn wind_speed(speed: &str) -> Option<f32> {
+    let values = speed
+        .split_whitespace()
+        .filter_map(|word| word.parse::<f32>().ok())
+        .collect::<Vec<_>>();
+
+    if values.is_empty() {
+        return None;
+    }
+
+    let mean = values.iter().sum::<f32>() / values.len() as f32;
+
+    if speed.ends_with("mph") {
+        Some(mean * 1.609_344 / KMH_IN_MS)
+    } else {
+        Some(mean / KMH_IN_MS)
+    }
+}
+
+/// Converts a 16-point compass direction to degrees. Returns `None` for unknown values.
+fn wind_direction(compass_point: &str) -> Option<f32> {
+    const POINTS: [&str; 16] = [
+        "N", "NNE", "NE", "ENE", "E", "ESE", "SE", "SSE", "S", "SSW", "SW", "WSW", "W", "WNW",
+        "NW", "NNW",
+    ];
+
+    POINTS
+        .iter()
+        .position(|&point| point == compass_point)
+        .map(|index| index as f32 * 22.5)
+}
+
+/// Maps NWS forecast text to the shared condition by keywords, the most specific first.
+/// Returns `None` for unknown texts.
+fn condition(short_forecast: &str) -> Option<Condition> {
+    const KEYWORDS: &[(&str, Condition)] = &[
+        ("thunder", Condition::Thunderstorm),
+        ("hail", Condition::Hail),
+        ("sleet", Condition::Sleet),
+        ("freezing", Condition::Sleet),
+        ("wintry mix", Condition::Sleet),
+        ("rain and snow", Condition::Sleet),
+        ("snow showers", Condition::SnowShowers),
+        ("heavy snow", Condition::HeavySnow),
+        ("light snow", Condition::LightSnow),
+        ("snow", Condition::Snow),
+        ("showers", Condition::Showers),
+        ("heavy rain", Condition::HeavyRain),
+        ("light rain", Condition::LightRain),
+        ("drizzle", Condition::Drizzle),
+        ("rain", Condition::Rain),
+        ("fog", Condition::Fog),
+        ("haze", Condition::Fog),
+        ("smoke", Condition::Fog),
+        ("overcast", Condition::Overcast),
+        ("partly", Condition::PartlyCloudy),
+        ("mostly sunny", Condition::PartlyCloudy),
+        ("mostly clear", Condition::PartlyCloudy),
+        ("cloudy", Condition::Cloudy),
+        ("sunny", Condition::Clear),
+        ("clear", Condition::Clear),
+    ];
+
+    let text = short_forecast.to_lowercase();
+
+    KEYWORDS
+        .iter()
+        .find(|(keyword, _)| text.contains(keyword))
+        .map(|&(_, condition)| condition)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fold_day_and_night_periods() {
+        let response: ForecastResponse = serde_json::from_str(
+            r#"{
+                "properties": {
+                    "periods": [
+                        {
+                            "startTime": "2019-03-11T18:00:00-05:00",
+                            "endTime": "2019-03-12T06:00:00-05:00",
+                            "isDaytime": false,
+                            "temperature": -2,
+                            "probabilityOfPrecipitation": { "value": null },
+                            "windSpeed": "5 km/h",
+                            "windDirection": "N",
+                            "shortForecast": "Mostly Clear"
+                        },
+                        {
+                            "startTime": "2019-03-12T06:00:00-05:00",
+                            "endTime": "2019-03-12T18:00:00-05:00",
+                            "isDaytime": true,
+                            "temperature": 12,
+                            "probabilityOfPrecipitation": { "value": 20 },
+                            "relativeHumidity": { "value": 70 },
+                            "windSpeed": "18 to 36 km/h",
+                            "windDirection": "NW",
+                            "shortForecast": "Chance Rain Showers"
+                        },
+                        {
+                            "startTime": "2019-03-12T18:00:00-05:00",
+                            "endTime": "2019-03-13T06:00:00-05:00",
+                            "isDaytime": false,
+                            "temperature": 3,
+                            "probabilityOfPrecipitation": { "value": 60 },
+                            "shortForecast": "Rain And Snow"
+                        }
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let forecasts = fold_daily(&response.properties.periods);
+        assert_eq!(forecasts.len(), 2);
+
+        // Tonight only.
+        let first = &forecasts[0];
+        assert_eq!(first.date, NaiveDate::from_ymd(2019, 3, 11));
+        assert_eq!(first.report.temperature, -2.0);
+        assert_eq!(first.report.temperature_max, None);
+        assert_eq!(first.report.condition, Some(Condition::PartlyCloudy));
+
+        let second = &forecasts[1];
+        assert_eq!(second.date, NaiveDate::from_ymd(2019, 3, 12));
+        assert_eq!(second.report.temperature, 12.0);
+        assert_eq!(second.report.temperature_min, Some(3.0));
+        assert_eq!(second.report.temperature_max, Some(12.0));
+        assert_eq!(second.report.humidity, Some(70.0));
+        assert_eq!(second.report.wind_speed, Some(7.5));
+        assert_eq!(second.report.wind_direction, Some(315.0));
+        assert_eq!(second.report.precipitation_probability, Some(60.0));
+        assert_eq!(second.report.condition, Some(Condition::Showers));
+
+        let (start, end) = second.period.unwrap();
+        assert_eq!(start.to_rfc3339(), "2019-03-12T11:00:00+00:00");
+        assert_eq!(end.to_rfc3339(), "2019-03-13T11:00:00+00:00");
+    }
+
+    #[test]
+    fn condition_mapping() {
+        assert_eq!(condition("Sunny"), Some(Condition::Clear));
+        assert_eq!(condition("Partly Cloudy"), Some(Condition::PartlyCloudy));
+        assert_eq!(condition("Mostly Cloudy"), Some(Condition::Cloudy));
+        assert_eq!(
+            condition("Slight Chance Light Rain"),
+            Some(Condition::LightRain)
+        );
+        assert_eq!(
+            condition("Showers And Thunderstorms Likely"),
+            Some(Condition::Thunderstorm)
+        );
+        assert_eq!(condition("Blowing Dust"), None);
+    }
+}
@@ -0,0 +1,265 @@
+use chrono::{FixedOffset, NaiveDate};
+use serde::Deserialize;
+
+use super::super::http::HttpClient;
+use super::super::provider::{Provider, ProviderError};
+use super::super::reporter::{Condition, DailyForecast, ProviderForecast, Report};
+
+// Open-Meteo forecasts cover up to 16 days.
+const MAX_DAYS: usize = 16;
+
+// Daily variables requested from Open-Meteo.
+const DAILY_VARIABLES: &str = "weather_code,temperature_2m_mean,temperature_2m_min,\
+    temperature_2m_max,relative_humidity_2m_mean,pressure_msl_mean,wind_speed_10m_mean,\
+    wind_direction_10m_dominant,wind_gusts_10m_max,precipitation_sum,\
+    precipitation_probability_max,cloud_cover_mean";
+
+pub struct OpenMeteo {
+    base_url: String,
+    client: HttpClient,
+}
+
+impl OpenMeteo {
+    pub fn new(base_url: &str, client: HttpClient) -> Self {
+        Self {
+            base_url: String::from(base_url.trim_end_matches('/')),
+            client,
+        }
+    }
+
+    /// Makes a call to Open-Meteo forecast API and returns the response.
+    /// The key is only needed for the commercial API.
+    fn get_forecast(
+        &self,
+        api_key: &str,
+        lat: f32,
+        lon: f32,
+        days: usize,
+    ) -> Result<ForecastResponse, ProviderError> {
+        let mut url = format!(
+            "{}/v1/forecast?latitude={}&longitude={}&daily={}&forecast_days={}\
+             &timezone=auto&wind_speed_unit=ms",
+            self.base_url,
+            lat,
+            lon,
+            DAILY_VARIABLES,
+            days.min(MAX_DAYS)
+        );
+
+        if !api_key.is_empty() {
+            url.push_str(&format!("&apikey={}", api_key));
+        }
+
+        let mut response = self.client.send(|client| client.get(url.as_str()))?;
+
+        match response.status() {
+            reqwest::StatusCode::OK => Ok(response.json()?),
+            status => Err(ProviderError::unexpected_status(status)),
+        }
+    }
+}
+
+impl Provider for OpenMeteo {
+    fn name(&self) -> String {
+        String::from("OpenMeteo")
+    }
+
+    /// Gets daily weather forecasts from Open-Meteo, parses the response and builds reports.
+    fn get_reports(
+        &self,
+        api_key: &str,
+        lat: f32,
+        lon: f32,
+        days: usize,
+    ) -> Result<ProviderForecast, ProviderError> {
+        let response = self.get_forecast(api_key, lat, lon, days)?;
+
+        Ok(ProviderForecast {
+            forecasts: response.daily.reports().into_iter().take(days).collect(),
+            utc_offset: response.utc_offset_seconds.and_then(FixedOffset::east_opt),
+        })
+    }
+}
+
+/*
+Structs for parsing Open-Meteo forecast API response with serde.
+Daily values come in parallel arrays indexed by the day, any value may be null.
+Example response (simplified):
+
+{
+    "timezone": "Europe/Moscow",
+    "utc_offset_seconds": 10800,
+    "daily": {
+        "time": ["2019-03-11", "2019-03-12"],
+        "weather_code": [71, 3],
+        "temperature_2m_mean": [-10.2, -8.4],
+        "temperature_2m_min": [-15.1, -12.0],
+        "temperature_2m_max": [-7.9, -5.3],
+        "relative_humidity_2m_mean": [76, 70],
+        "pressure_msl_mean": [1012.4, 1015.0],
+        "wind_speed_10m_mean": [3.5, 2.1],
+        "wind_direction_10m_dominant": [315, 290],
+        "wind_gusts_10m_max": [7.2, 5.0],
+        "precipitation_sum": [1.2, 0.0],
+        "precipitation_probability_max": [40, 5],
+        "cloud_cover_mean": [75, 100]
+    }
+}
+*/
+
+#[derive(Debug, Deserialize)]
+struct ForecastResponse {
+    /// Offset of the location's time zone with `timezone=auto`.
+    utc_offset_seconds: Option<i32>,
+    daily: Daily,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Daily {
+    time: Vec<NaiveDate>,
+    /// WMO weather interpretation code.
+    weather_code: Vec<Option<u32>>,
+    /// °C.
+    temperature_2m_mean: Vec<Option<f32>>,
+    /// °C.
+    temperature_2m_min: Vec<Option<f32>>,
+    /// °C.
+    temperature_2m_max: Vec<Option<f32>>,
+    /// %.
+    relative_humidity_2m_mean: Vec<Option<f32>>,
+    /// hPa.
+    pressure_msl_mean: Vec<Option<f32>>,
+    /// m/s.
+    wind_speed_10m_mean: Vec<Option<f32>>,
+    /// Degrees.
+    wind_direction_10m_dominant: Vec<Option<f32>>,
+    /// m/s.
+    wind_gusts_10m_max: Vec<Option<f32>>,
+    /// mm.
+    precipitation_sum: Vec<Option<f32>>,
+    /// %.
+    precipitation_probability_max: Vec<Option<f32>>,
+    /// %.
+    cloud_cover_mean: Vec<Option<f32>>,
+}
+
+impl Daily {
+    /// Builds a report for each day. The temperature is the daily mean or, when it's missing,
+    /// the middle between the minimum and the maximum. Days without temperature are skipped.
+    fn reports(&self) -> Vec<DailyForecast> {
+        self.time
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &date)| {
+                let min = value(&self.temperature_2m_min, i);
+                let max = value(&self.temperature_2m_max, i);
+                let temperature =
+                    value(&self.temperature_2m_mean, i).or_else(|| match (min, max) {
+                        (Some(min), Some(max)) => Some((min + max) / 2.0),
+                        _ => None,
+                    })?;
+
+                let report = Report {
+                    temperature_min: min,
+                    temperature_max: max,
+                    humidity: value(&self.relative_humidity_2m_mean, i),
+                    pressure: value(&self.pressure_msl_mean, i),
+                    wind_speed: value(&self.wind_speed_10m_mean, i),
+                    wind_direction: value(&self.wind_direction_10m_dominant, i),
+                    wind_gust: value(&self.wind_gusts_10m_max, i),
+                    precipitation: value(&self.precipitation_sum, i),
+                    precipitation_probability: value(&self.precipitation_probability_max, i),
+                    cloud_cover: value(&self.cloud_cover_mean, i),
+                    condition: value(&self.weather_code, i).and_then(condition),
+                    ..Report::new(temperature)
+                };
+
+                Some(DailyForecast::new(date, report))
+            })
+            .collect()
+    }
+}
+
+/// Returns the value for the day at `index` if it's present.
+fn value<T: Copy>(values: &[Option<T>], index: usize) -> Option<T> {
+    values.get(index).copied().flatten()
+}
+
+/// Maps a WMO weather interpretation code to the shared condition.
+/// Returns `None` for unknown codes.
+fn condition(code: u32) -> Option<Condition> {
+    let condition = match code {
+        0 => Condition::Clear,
+        1 | 2 => Condition::PartlyCloudy,
+        3 => Condition::Overcast,
+        45 | 48 => Condition::Fog,
+        51 | 53 | 55 => Condition::Drizzle,
+        // Freezing drizzle and rain.
+        56 | 57 | 66 | 67 => Condition::Sleet,
+        61 => Condition::LightRain,
+        63 => Condition::Rain,
+        65 => Condition::HeavyRain,
+        71 => Condition::LightSnow,
+        73 | 77 => Condition::Snow,
+        75 => Condition::HeavySnow,
+        80..=82 => Condition::Showers,
+        85 | 86 => Condition::SnowShowers,
+        95 | 96 | 99 => Condition::Thunderstorm,
+        _ => return None,
+    };
+
+    Some(condition)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn daily_reports() {
+        let response: ForecastResponse = serde_json::from_str(
+            r#"{
+                "daily": {
+                    "time": ["2019-03-11", "2019-03-12", "2019-03-13"],
+                    "weather_code": [71, 3, null],
+                    "temperature_2m_mean": [-10.25, null, null],
+                    "temperature_2m_min": [-15.5, -12.0, null],
+                    "temperature_2m_max": [-7.5, -5.0, null],
+                    "wind_direction_10m_dominant": [315, 290, null],
+                    "precipitation_sum": [1.25, 0.0, null],
+                    "cloud_cover_mean": [75, 100, null]
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let reports = response.daily.reports();
+        // The last day has no temperature.
+        assert_eq!(reports.len(), 2);
+
+        let first = &reports[0];
+        assert_eq!(first.date, NaiveDate::from_ymd(2019, 3, 11));
+        assert_eq!(first.report.temperature, -10.25);
+        assert_eq!(first.report.temperature_min, Some(-15.5));
+        assert_eq!(first.report.temperature_max, Some(-7.5));
+        assert_eq!(first.report.wind_direction, Some(315.0));
+        assert_eq!(first.report.precipitation, Some(1.25));
+        assert_eq!(first.report.cloud_cover, Some(75.0));
+        assert_eq!(first.report.humidity, None);
+        assert_eq!(first.report.condition, Some(Condition::LightSnow));
+
+        // The mean is taken from the minimum and the maximum.
+        assert_eq!(reports[1].report.temperature, -8.5);
+        assert_eq!(reports[1].report.condition, Some(Condition::Overcast));
+    }
+
+    #[test]
+    fn condition_mapping() {
+        assert_eq!(condition(0), Some(Condition::Clear));
+        assert_eq!(condition(66), Some(Condition::Sleet));
+        assert_eq!(condition(81), Some(Condition::Showers));
+        assert_eq!(condition(99), Some(Condition::Thunderstorm));
+        assert_eq!(condition(42), None);
+    }
+}
@@ -1,41 +1,46 @@
-use std::error::Error;
-
-use reqwest::Client;
+use chrono::prelude::*;
+use chrono::Duration;
 use serde::Deserialize;
 
-use super::super::provider::Provider;
-use super::super::reporter::Report;
+use super::super::http::HttpClient;
+use super::super::provider::{Provider, ProviderError};
+use super::super::reporter::{Condition, DailyForecast, ProviderForecast, Report};
+use super::daily::{self, Slot};
+
+// OpenWeatherMap forecasts come in 3-hour slots.
+const SLOT_HOURS: i64 = 3;
 
 pub struct OpenWeatherMap {
-    api_key: String,
-    client: Client,
+    base_url: String,
+    client: HttpClient,
 }
 
 impl OpenWeatherMap {
-    pub fn new(api_key: &str) -> Self {
+    pub fn new(base_url: &str, client: HttpClient) -> Self {
         Self {
-            api_key: String::from(api_key),
-            client: Client::new(),
+            base_url: String::from(base_url.trim_end_matches('/')),
+            client,
         }
     }
 
     /// Makes a call to OpenWeatherMap weather forecast API and returns the response.
+    /// The response contains the whole available series of 3-hour slots (5 days).
     fn get_forecast(
         &self,
+        api_key: &str,
         lat: f32,
         lon: f32,
-        days: usize,
-    ) -> Result<ForecastResponse, Box<dyn Error>> {
+    ) -> Result<ForecastResponse, ProviderError> {
         let url = format!(
-            "https://api.openweathermap.org/data/2.5/forecast?lat={}&lon={}&cnt={}&units=metric&appid={}",
-            lat, lon, days, self.api_key
+            "{}/data/2.5/forecast?lat={}&lon={}&units=metric&appid={}",
+            self.base_url, lat, lon, api_key
         );
 
-        let mut response = self.client.get(url.as_str()).send()?;
+        let mut response = self.client.send(|client| client.get(url.as_str()))?;
 
         match response.status() {
             reqwest::StatusCode::OK => Ok(response.json()?),
-            status => bail!("Unexpected HTTP status {}", status.as_u16()),
+            status => Err(ProviderError::unexpected_status(status)),
         }
     }
 }
@@ -46,15 +51,57 @@ impl Provider for OpenWeatherMap {
     }
 
     /// Gets weather forecasts from OpenWeatherMap, parses the response and builds reports.
-    fn get_reports(&self, lat: f32, lon: f32, days: usize) -> Result<Vec<Report>, Box<dyn Error>> {
-        let reports = self
-            .get_forecast(lat, lon, days)?
-            .list
-            .iter()
-            .map(|forecast| Report::new(forecast.main.temp))
-            .collect();
-
-        Ok(reports)
+    fn get_reports(
+        &self,
+        api_key: &str,
+        lat: f32,
+        lon: f32,
+        days: usize,
+    ) -> Result<ProviderForecast, ProviderError> {
+        let response = self.get_forecast(api_key, lat, lon)?;
+
+        Ok(ProviderForecast {
+            forecasts: fold_daily(&response).into_iter().take(days).collect(),
+            utc_offset: Some(FixedOffset::east(response.city.timezone)),
+        })
+    }
+}
+
+/// Converts OpenWeatherMap 3-hour slots and folds them into days
+/// in the location's time zone.
+fn fold_daily(response: &ForecastResponse) -> Vec<DailyForecast> {
+    let offset = FixedOffset::east(response.city.timezone);
+    let slots = response.list.iter().map(Forecast::slot).collect();
+    daily::fold_daily(slots, offset)
+}
+
+impl Forecast {
+    fn slot(&self) -> Slot {
+        let start = Utc.timestamp(self.dt, 0);
+
+        let precipitation = match (&self.rain, &self.snow) {
+            (None, None) => None,
+            (rain, snow) => Some(Volume::amount(rain) + Volume::amount(snow)),
+        };
+
+        let report = Report {
+            humidity: self.main.humidity,
+            pressure: self.main.pressure,
+            wind_speed: self.wind.as_ref().map(|w| w.speed),
+            wind_direction: self.wind.as_ref().and_then(|w| w.deg),
+            wind_gust: self.wind.as_ref().and_then(|w| w.gust),
+            precipitation,
+            precipitation_probability: self.pop.map(|pop| pop * 100.0),
+            cloud_cover: self.clouds.as_ref().map(|c| c.all),
+            condition: self.weather.first().and_then(|w| condition(w.id)),
+            ..Report::new(self.main.temp)
+        };
+
+        Slot {
+            start,
+            end: start + Duration::hours(SLOT_HOURS),
+            report,
+        }
     }
 }
 
@@ -65,25 +112,208 @@ Example response (simplified):
 {
     "list": [
         {
+            "dt": 1552305600,
             "main": {
-                "temp": -3.5
-            }
+                "temp": -3.5,
+                "pressure": 1021.6,
+                "humidity": 85
+            },
+            "weather": [
+                {
+                    "id": 600
+                }
+            ],
+            "clouds": {
+                "all": 92
+            },
+            "wind": {
+                "speed": 4.1,
+                "deg": 250,
+                "gust": 8.3
+            },
+            "snow": {
+                "3h": 0.3
+            },
+            "pop": 0.4
         }
-    ]
+    ],
+    "city": {
+        "timezone": 10800
+    }
 }
 */
 
 #[derive(Debug, Deserialize)]
 struct ForecastResponse {
     list: Vec<Forecast>,
+    city: City,
 }
 
 #[derive(Debug, Deserialize)]
 struct Forecast {
+    dt: i64,
     main: Main,
+    #[serde(default)]
+    weather: Vec<Weather>,
+    clouds: Option<Clouds>,
+    wind: Option<Wind>,
+    rain: Option<Volume>,
+    snow: Option<Volume>,
+    /// Probability of precipitation from 0 to 1.
+    pop: Option<f32>,
 }
 
 #[derive(Debug, Deserialize)]
 struct Main {
     temp: f32,
+    /// hPa.
+    pressure: Option<f32>,
+    /// %.
+    humidity: Option<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Weather {
+    /// Weather condition code.
+    id: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct Clouds {
+    /// %.
+    all: f32,
+}
+
+#[derive(Debug, Deserialize)]
+struct Wind {
+    /// m/s.
+    speed: f32,
+    /// Degrees.
+    deg: Option<f32>,
+    /// m/s.
+    gust: Option<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Volume {
+    /// mm for the last 3 hours.
+    #[serde(rename = "3h")]
+    three_hours: Option<f32>,
+}
+
+impl Volume {
+    fn amount(volume: &Option<Volume>) -> f32 {
+        volume.as_ref().and_then(|v| v.three_hours).unwrap_or(0.0)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct City {
+    /// Shift in seconds from UTC.
+    timezone: i32,
+}
+
+/// Maps an OpenWeatherMap weather condition code to the shared condition.
+/// See https://openweathermap.org/weather-conditions. Returns `None` for unknown codes.
+fn condition(id: u32) -> Option<Condition> {
+    let condition = match id {
+        200..=299 => Condition::Thunderstorm,
+        300..=399 => Condition::Drizzle,
+        500 => Condition::LightRain,
+        501 => Condition::Rain,
+        502..=504 => Condition::HeavyRain,
+        511 => Condition::Sleet,
+        520..=531 => Condition::Showers,
+        600 => Condition::LightSnow,
+        601 => Condition::Snow,
+        602 => Condition::HeavySnow,
+        611..=616 => Condition::Sleet,
+        620..=622 => Condition::SnowShowers,
+        700..=799 => Condition::Fog,
+        800 => Condition::Clear,
+        801 => Condition::PartlyCloudy,
+        802 | 803 => Condition::Cloudy,
+        804 => Condition::Overcast,
+        _ => return None,
+    };
+
+    Some(condition)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::config;
+    use super::*;
+
+    #[test]
+    fn transport_error_hides_api_key() {
+        let client = HttpClient::new(&config::Http {
+            max_retries: 0,
+            ..config::Http::default()
+        });
+        // Connections to port 0 are refused.
+        let provider = OpenWeatherMap::new("http://127.0.0.1:0", client);
+
+        let error = provider
+            .get_reports("secret-key", 55.75, 37.62, 1)
+            .unwrap_err();
+        assert!(matches!(error, ProviderError::Http(_)));
+        assert!(!error.to_string().is_empty());
+        assert!(!error.to_string().contains("secret-key"));
+    }
+
+    #[test]
+    fn fold_daily_in_local_time_zone() {
+        // UTC+3, so 21:00 UTC is the next local day.
+        let response: ForecastResponse = serde_json::from_str(
+            r#"{
+                "list": [
+                    {
+                        "dt": 1552327200,
+                        "main": { "temp": -2.0, "humidity": 80 },
+                        "weather": [{ "id": 600 }],
+                        "snow": { "3h": 0.5 },
+                        "pop": 0.5
+                    },
+                    {
+                        "dt": 1552338000,
+                        "main": { "temp": -6.0, "humidity": 90 },
+                        "weather": [{ "id": 600 }],
+                        "rain": { "3h": 0.25 },
+                        "pop": 0.25
+                    },
+                    {
+                        "dt": 1552348800,
+                        "main": { "temp": 3.0, "humidity": 70 },
+                        "weather": [{ "id": 804 }],
+                        "wind": { "speed": 3.0, "deg": 180, "gust": 7.0 }
+                    }
+                ],
+                "city": { "timezone": 10800 }
+            }"#,
+        )
+        .unwrap();
+
+        let forecasts = fold_daily(&response);
+        assert_eq!(forecasts.len(), 2);
+
+        // 2019-03-11 21:00 local, evening only.
+        let first = &forecasts[0];
+        assert_eq!(first.date, NaiveDate::from_ymd(2019, 3, 11));
+        assert_eq!(first.report.temperature, -2.0);
+        assert_eq!(first.report.precipitation, Some(0.5));
+        assert_eq!(first.report.precipitation_probability, Some(50.0));
+
+        // 2019-03-12 00:00 and 03:00 local.
+        let second = &forecasts[1];
+        assert_eq!(second.date, NaiveDate::from_ymd(2019, 3, 12));
+        assert_eq!(second.report.temperature, -1.5);
+        assert_eq!(second.report.temperature_min, Some(-6.0));
+        assert_eq!(second.report.temperature_max, Some(3.0));
+        assert_eq!(second.report.humidity, Some(80.0));
+        assert_eq!(second.report.wind_gust, Some(7.0));
+        assert_eq!(second.report.precipitation, Some(0.25));
+        assert_eq!(second.report.precipitation_probability, Some(25.0));
+        assert_eq!(second.report.condition, Some(Condition::LightSnow));
+    }
 }
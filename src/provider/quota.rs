@@ -0,0 +1,292 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use super::super::config;
+
+// How often changed buckets are saved, see `QuotaStore::start_saving`.
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Token buckets of all quotas persisted in a JSON file to survive restarts.
+pub struct QuotaStore {
+    path: String,
+    buckets: Mutex<HashMap<String, BTreeMap<String, Bucket>>>,
+    /// Whether the buckets changed since the last save.
+    changed: AtomicBool,
+    /// Whether the saved state was lost, so new buckets start spent rather than full.
+    lost: bool,
+}
+
+/// A token bucket for a single window like "minute".
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+struct Bucket {
+    tokens: f64,
+    /// Time of the last refill, ms since the Unix epoch.
+    updated_at_ms: u64,
+}
+
+impl QuotaStore {
+    /// Loads the state from the file at `path`. Starts from full buckets when the file
+    /// is missing. When the file can't be read or parsed, starts from spent buckets
+    /// refilling over their windows to avoid spending the budgets twice.
+    pub fn load(path: &str) -> Self {
+        let lost = |error: &dyn Error| {
+            println!(
+                "Error loading quota state from {}, budgets start spent: {}",
+                path, error
+            );
+            (HashMap::new(), true)
+        };
+
+        let (buckets, lost) = match fs::read_to_string(path) {
+            Ok(contents) => match serde_json::from_str(&contents) {
+                Ok(buckets) => (buckets, false),
+                Err(error) => lost(&error),
+            },
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => (HashMap::new(), false),
+            Err(error) => lost(&error),
+        };
+
+        Self {
+            path: String::from(path),
+            buckets: Mutex::new(buckets),
+            changed: AtomicBool::new(false),
+            lost,
+        }
+    }
+
+    /// Saves the state every `SAVE_INTERVAL` while the store is in use.
+    pub fn start_saving(store: &Arc<Self>) {
+        let store = Arc::downgrade(store);
+
+        thread::spawn(move || loop {
+            thread::sleep(SAVE_INTERVAL);
+
+            match store.upgrade() {
+                Some(store) => store.save(),
+                None => return,
+            }
+        });
+    }
+
+    /// Saves the state if it changed since the last save. Writes a temporary file and renames
+    /// it over the state file, so that a crash never leaves the state file torn.
+    fn save(&self) {
+        if !self.changed.swap(false, Ordering::SeqCst) {
+            return;
+        }
+
+        let temp_path = format!("{}.tmp", self.path);
+        let result = serde_json::to_string(&*self.buckets.lock().unwrap())
+            .map_err(|error| error.to_string())
+            .and_then(|json| {
+                fs::write(&temp_path, json)
+                    .and_then(|_| fs::rename(&temp_path, &self.path))
+                    .map_err(|error| error.to_string())
+            });
+
+        if let Err(error) = result {
+            println!("Error saving quota state to {}: {}", self.path, error);
+        }
+    }
+}
+
+impl Drop for QuotaStore {
+    fn drop(&mut self) {
+        self.save();
+    }
+}
+
+/// Request budget of a provider's API key enforced with token buckets: one for each limited
+/// window. A bucket holds up to `limit` tokens and refills at `limit` tokens per window.
+pub struct Quota {
+    key: String,
+    limits: Vec<(&'static str, u64, u32)>,
+    store: Arc<QuotaStore>,
+}
+
+impl Quota {
+    /// Creates a quota for `provider` with `api_key`. The key is stored as a fingerprint
+    /// to keep it out of the state file.
+    pub fn new(
+        provider: &str,
+        api_key: &str,
+        quota_config: &config::Quota,
+        store: Arc<QuotaStore>,
+    ) -> Self {
+        let limits = vec![
+            ("second", 1, quota_config.per_second),
+            ("minute", 60, quota_config.per_minute),
+            ("day", 24 * 60 * 60, quota_config.per_day),
+        ]
+        .into_iter()
+        .filter_map(|(window, seconds, limit)| limit.map(|limit| (window, seconds, limit)))
+        .collect();
+
+        Self {
+            key: format!("{}:{:016x}", provider, fingerprint(api_key)),
+            limits,
+            store,
+        }
+    }
+
+    /// Takes a token from each bucket when all of them have one. Otherwise returns `false`
+    /// and leaves the buckets intact.
+    pub fn try_acquire(&self) -> bool {
+        self.try_acquire_at(SystemTime::now())
+    }
+
+    fn try_acquire_at(&self, now: SystemTime) -> bool {
+        let now_ms = now
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+
+        let mut all_buckets = self.store.buckets.lock().unwrap();
+        let buckets = all_buckets.entry(self.key.clone()).or_default();
+
+        for &(window, seconds, limit) in &self.limits {
+            let bucket = buckets.entry(String::from(window)).or_insert(Bucket {
+                tokens: if self.store.lost {
+                    0.0
+                } else {
+                    f64::from(limit)
+                },
+                updated_at_ms: now_ms,
+            });
+
+            let elapsed = now_ms.saturating_sub(bucket.updated_at_ms) as f64 / 1000.0;
+            let refill = elapsed * f64::from(limit) / seconds as f64;
+            bucket.tokens = (bucket.tokens + refill).min(f64::from(limit));
+            bucket.updated_at_ms = now_ms;
+        }
+
+        let allowed = self
+            .limits
+            .iter()
+            .all(|(window, _, _)| buckets[*window].tokens >= 1.0);
+
+        if allowed {
+            for (window, _, _) in &self.limits {
+                if let Some(bucket) = buckets.get_mut(*window) {
+                    bucket.tokens -= 1.0;
+                }
+            }
+
+            self.store.changed.store(true, Ordering::SeqCst);
+        }
+
+        allowed
+    }
+}
+
+/// FNV-1a hash which, unlike `DefaultHasher`, is stable between builds.
+fn fingerprint(value: &str) -> u64 {
+    value.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+    use std::time::Duration;
+
+    use super::*;
+
+    fn state_path(name: &str) -> String {
+        let file_name = format!("weather_report_quota_{}_{}.json", name, process::id());
+        env::temp_dir()
+            .join(file_name)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn quota(path: &str) -> Quota {
+        quota_in(Arc::new(QuotaStore::load(path)))
+    }
+
+    fn quota_in(store: Arc<QuotaStore>) -> Quota {
+        let quota_config = config::Quota {
+            per_second: None,
+            per_minute: Some(2),
+            per_day: Some(3),
+        };
+
+        Quota::new("Yandex", "key", &quota_config, store)
+    }
+
+    #[test]
+    fn token_buckets() {
+        let path = state_path("buckets");
+        let quota = quota(&path);
+        let now = SystemTime::now();
+
+        // The minute budget is spent.
+        assert!(quota.try_acquire_at(now));
+        assert!(quota.try_acquire_at(now));
+        assert!(!quota.try_acquire_at(now));
+
+        // A token is back in 30 seconds, then the day budget is spent.
+        let later = now + Duration::from_secs(30);
+        assert!(quota.try_acquire_at(later));
+        assert!(!quota.try_acquire_at(later + Duration::from_secs(60)));
+
+        fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn persistence() {
+        let path = state_path("persistence");
+        let now = SystemTime::now();
+        assert!(quota(&path).try_acquire_at(now));
+        assert!(quota(&path).try_acquire_at(now));
+
+        // Restarted with the spent budget.
+        assert!(!quota(&path).try_acquire_at(now));
+        assert!(!fs::read_to_string(&path).unwrap().contains("key"));
+
+        fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn saving() {
+        let path = state_path("saving");
+        fs::remove_file(&path).ok();
+        let store = Arc::new(QuotaStore::load(&path));
+        let quota = quota_in(Arc::clone(&store));
+
+        // Tokens are saved in the background rather than by each call.
+        assert!(quota.try_acquire_at(SystemTime::now()));
+        assert!(fs::metadata(&path).is_err());
+
+        store.save();
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(serde_json::from_str::<serde_json::Value>(&contents).is_ok());
+        assert!(fs::metadata(format!("{}.tmp", path)).is_err());
+
+        fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn broken_state() {
+        let path = state_path("broken");
+        fs::write(&path, r#"{"Yandex:"#).unwrap();
+        let quota = quota(&path);
+        let now = SystemTime::now();
+
+        // Budgets start spent and refill over their windows rather than start full.
+        assert!(!quota.try_acquire_at(now));
+        assert!(quota.try_acquire_at(now + Duration::from_secs(8 * 60 * 60)));
+
+        fs::remove_file(path).ok();
+    }
+}
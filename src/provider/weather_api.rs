@@ -0,0 +1,256 @@
+use chrono::prelude::*;
+use serde::Deserialize;
+
+use super::super::http::HttpClient;
+use super::super::provider::{Provider, ProviderError};
+use super::super::reporter::{Condition, DailyForecast, ProviderForecast, Report};
+
+pub struct WeatherApi {
+    base_url: String,
+    client: HttpClient,
+}
+
+impl WeatherApi {
+    pub fn new(base_url: &str, client: HttpClient) -> Self {
+        Self {
+            base_url: String::from(base_url.trim_end_matches('/')),
+            client,
+        }
+    }
+
+    /// Makes a call to WeatherAPI.com forecast API and returns the response.
+    fn get_forecast(
+        &self,
+        api_key: &str,
+        lat: f32,
+        lon: f32,
+        days: usize,
+    ) -> Result<ForecastResponse, ProviderError> {
+        let url = format!(
+            "{}/v1/forecast.json?key={}&q={},{}&days={}&aqi=no&alerts=no",
+            self.base_url, api_key, lat, lon, days
+        );
+
+        let mut response = self.client.send(|client| client.get(url.as_str()))?;
+
+        match response.status() {
+            reqwest::StatusCode::OK => Ok(response.json()?),
+            status => Err(ProviderError::unexpected_status(status)),
+        }
+    }
+}
+
+impl Provider for WeatherApi {
+    fn name(&self) -> String {
+        String::from("WeatherApi")
+    }
+
+    /// Gets daily forecasts from WeatherAPI.com, parses the response and builds reports.
+    fn get_reports(
+        &self,
+        api_key: &str,
+        lat: f32,
+        lon: f32,
+        days: usize,
+    ) -> Result<ProviderForecast, ProviderError> {
+        let response = self.get_forecast(api_key, lat, lon, days)?;
+
+        let forecasts = response
+            .forecast
+            .forecastday
+            .iter()
+            .map(|forecast| DailyForecast::new(forecast.date, forecast.day.report()))
+            .collect();
+
+        Ok(ProviderForecast {
+            forecasts,
+            utc_offset: response.location.as_ref().and_then(Location::utc_offset),
+        })
+    }
+}
+
+/*
+Structs for parsing WeatherAPI.com forecast API response with serde.
+Example response (simplified):
+
+{
+    "location": { "localtime_epoch": 1552305900, "localtime": "2019-03-11 15:05" },
+    "forecast": {
+        "forecastday": [
+            {
+                "date": "2019-03-11",
+                "day": {
+                    "maxtemp_c": -8.0,
+                    "mintemp_c": -15.0,
+                    "avgtemp_c": -10.5,
+                    "totalprecip_mm": 1.2,
+                    "avghumidity": 76,
+                    "daily_chance_of_rain": 10,
+                    "daily_chance_of_snow": 40,
+                    "condition": { "text": "Light snow", "code": 1213 }
+                }
+            }
+        ]
+    }
+}
+*/
+
+#[derive(Debug, Deserialize)]
+struct ForecastResponse {
+    location: Option<Location>,
+    forecast: Forecast,
+}
+
+#[derive(Debug, Deserialize)]
+struct Location {
+    /// Current time as a Unix timestamp.
+    localtime_epoch: i64,
+    /// Current local time like "2019-03-11 15:05".
+    localtime: String,
+}
+
+impl Location {
+    /// Tells the UTC offset by the difference of the local time and the timestamp
+    /// rounded to 15 minutes.
+    fn utc_offset(&self) -> Option<FixedOffset> {
+        let local = NaiveDateTime::parse_from_str(&self.localtime, "%Y-%m-%d %H:%M").ok()?;
+        let utc = Utc.timestamp(self.localtime_epoch, 0).naive_utc();
+        let seconds = (local - utc).num_seconds() as f32;
+        FixedOffset::east_opt((seconds / 900.0).round() as i32 * 900)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Forecast {
+    forecastday: Vec<ForecastDay>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ForecastDay {
+    date: NaiveDate,
+    day: Day,
+}
+
+#[derive(Debug, Deserialize)]
+struct Day {
+    /// °C.
+    avgtemp_c: f32,
+    /// °C.
+    mintemp_c: Option<f32>,
+    /// °C.
+    maxtemp_c: Option<f32>,
+    /// mm.
+    totalprecip_mm: Option<f32>,
+    /// %.
+    avghumidity: Option<f32>,
+    /// %.
+    daily_chance_of_rain: Option<f32>,
+    /// %.
+    daily_chance_of_snow: Option<f32>,
+    condition: Option<WeatherCondition>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WeatherCondition {
+    code: u32,
+}
+
+impl Day {
+    /// Builds a daily report. The precipitation probability is the bigger one of rain and snow.
+    fn report(&self) -> Report {
+        let precipitation_probability = match (self.daily_chance_of_rain, self.daily_chance_of_snow)
+        {
+            (Some(rain), Some(snow)) => Some(rain.max(snow)),
+            (rain, snow) => rain.or(snow),
+        };
+
+        Report {
+            temperature_min: self.mintemp_c,
+            temperature_max: self.maxtemp_c,
+            humidity: self.avghumidity,
+            precipitation: self.totalprecip_mm,
+            precipitation_probability,
+            condition: self.condition.as_ref().and_then(|c| condition(c.code)),
+            ..Report::new(self.avgtemp_c)
+        }
+    }
+}
+
+/// Maps a WeatherAPI.com condition code to the shared condition.
+/// See https://www.weatherapi.com/docs/weather_conditions.json. Returns `None` for unknown codes.
+fn condition(code: u32) -> Option<Condition> {
+    let condition = match code {
+        1000 => Condition::Clear,
+        1003 => Condition::PartlyCloudy,
+        1006 => Condition::Cloudy,
+        1009 => Condition::Overcast,
+        1030 | 1135 | 1147 => Condition::Fog,
+        1063 | 1180 | 1183 => Condition::LightRain,
+        1186 | 1189 => Condition::Rain,
+        1192 | 1195 => Condition::HeavyRain,
+        1150 | 1153 => Condition::Drizzle,
+        1240..=1246 => Condition::Showers,
+        1066 | 1210 | 1213 => Condition::LightSnow,
+        1114 | 1216 | 1219 => Condition::Snow,
+        1117 | 1222 | 1225 => Condition::HeavySnow,
+        1255 | 1258 => Condition::SnowShowers,
+        // Sleet, freezing drizzle and freezing rain.
+        1069 | 1072 | 1168 | 1171 | 1198..=1207 | 1249 | 1252 => Condition::Sleet,
+        // Ice pellets.
+        1237 | 1261 | 1264 => Condition::Hail,
+        1087 | 1273..=1282 => Condition::Thunderstorm,
+        _ => return None,
+    };
+
+    Some(condition)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn day_report() {
+        let day: Day = serde_json::from_str(
+            r#"{
+                "maxtemp_c": -8.0,
+                "mintemp_c": -15.0,
+                "avgtemp_c": -10.5,
+                "totalprecip_mm": 1.25,
+                "avghumidity": 76,
+                "daily_chance_of_rain": 10,
+                "daily_chance_of_snow": 40,
+                "condition": { "text": "Light snow", "code": 1213 }
+            }"#,
+        )
+        .unwrap();
+
+        let report = day.report();
+        assert_eq!(report.temperature, -10.5);
+        assert_eq!(report.temperature_min, Some(-15.0));
+        assert_eq!(report.temperature_max, Some(-8.0));
+        assert_eq!(report.humidity, Some(76.0));
+        assert_eq!(report.precipitation, Some(1.25));
+        assert_eq!(report.precipitation_probability, Some(40.0));
+        assert_eq!(report.condition, Some(Condition::LightSnow));
+    }
+
+    #[test]
+    fn location_utc_offset() {
+        let location = Location {
+            localtime_epoch: 1552305900,
+            localtime: String::from("2019-03-11 15:05"),
+        };
+
+        assert_eq!(location.utc_offset(), Some(FixedOffset::east(3 * 3600)));
+    }
+
+    #[test]
+    fn condition_mapping() {
+        assert_eq!(condition(1000), Some(Condition::Clear));
+        assert_eq!(condition(1201), Some(Condition::Sleet));
+        assert_eq!(condition(1243), Some(Condition::Showers));
+        assert_eq!(condition(1276), Some(Condition::Thunderstorm));
+        assert_eq!(condition(42), None);
+    }
+}
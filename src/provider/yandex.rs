@@ -1,45 +1,43 @@
-use std::error::Error;
-
-use reqwest::Client;
+use chrono::{FixedOffset, NaiveDate};
 use serde::Deserialize;
 
-use super::super::provider::Provider;
-use super::super::reporter::Report;
+use super::super::http::HttpClient;
+use super::super::provider::{Provider, ProviderError};
+use super::super::reporter::{Condition, DailyForecast, ProviderForecast, Report};
 
 pub struct Yandex {
-    api_key: String,
-    client: Client,
+    base_url: String,
+    client: HttpClient,
 }
 
 impl Yandex {
-    pub fn new(api_key: &str) -> Self {
+    pub fn new(base_url: &str, client: HttpClient) -> Self {
         Self {
-            api_key: String::from(api_key),
-            client: Client::new(),
+            base_url: String::from(base_url.trim_end_matches('/')),
+            client,
         }
     }
 
     /// Makes a call to Yandex weather forecast API and returns the response.
     fn get_forecast(
         &self,
+        api_key: &str,
         lat: f32,
         lon: f32,
         days: usize,
-    ) -> Result<ForecastResponse, Box<dyn Error>> {
+    ) -> Result<ForecastResponse, ProviderError> {
         let url = format!(
-            "https://api.weather.yandex.ru/v1/forecast?lat={}&lon={}&limit={}",
-            lat, lon, days
+            "{}/v1/forecast?lat={}&lon={}&limit={}",
+            self.base_url, lat, lon, days
         );
 
         let mut response = self
             .client
-            .get(url.as_str())
-            .header("X-Yandex-API-Key", self.api_key.clone())
-            .send()?;
+            .send(|client| client.get(url.as_str()).header("X-Yandex-API-Key", api_key))?;
 
         match response.status() {
             reqwest::StatusCode::OK => Ok(response.json()?),
-            status => bail!("Unexpected HTTP status {}", status.as_u16()),
+            status => Err(ProviderError::unexpected_status(status)),
         }
     }
 }
@@ -50,15 +48,30 @@ impl Provider for Yandex {
     }
 
     /// Gets weather forecasts from Yandex, parses the response and builds reports.
-    fn get_reports(&self, lat: f32, lon: f32, days: usize) -> Result<Vec<Report>, Box<dyn Error>> {
-        let reports = self
-            .get_forecast(lat, lon, days)?
+    fn get_reports(
+        &self,
+        api_key: &str,
+        lat: f32,
+        lon: f32,
+        days: usize,
+    ) -> Result<ProviderForecast, ProviderError> {
+        let response = self.get_forecast(api_key, lat, lon, days)?;
+
+        let forecasts = response
             .forecasts
             .iter()
-            .map(|forecast| Report::new(forecast.parts.day.temp_avg as f32))
+            .map(|forecast| DailyForecast::new(forecast.date, forecast.parts.report()))
             .collect();
 
-        Ok(reports)
+        let utc_offset = response
+            .info
+            .and_then(|info| info.tzinfo)
+            .and_then(|tzinfo| FixedOffset::east_opt(tzinfo.offset));
+
+        Ok(ProviderForecast {
+            forecasts,
+            utc_offset,
+        })
     }
 }
 
@@ -67,12 +80,30 @@ Structs for parsing Yandex weather API response with serde.
 Example response (simplified):
 
 {
+    "info": {
+        "tzinfo": { "name": "Europe/Moscow", "offset": 10800 }
+    },
     "forecasts": [
         {
+            "date": "2019-03-11",
             "parts": {
+                "night": { "temp_min": -15, "temp_max": -12, "temp_avg": -13, "prec_mm": 0.4 },
+                "morning": { "temp_min": -14, "temp_max": -11, "temp_avg": -12 },
                 "day": {
-                    "temp_avg": -10
-                }
+                    "temp_min": -12,
+                    "temp_max": -8,
+                    "temp_avg": -10,
+                    "humidity": 76,
+                    "pressure_pa": 1012,
+                    "wind_speed": 3.5,
+                    "wind_gust": 7.2,
+                    "wind_dir": "nw",
+                    "prec_mm": 1.2,
+                    "prec_prob": 40,
+                    "cloudness": 0.75,
+                    "condition": "overcast-and-light-snow"
+                },
+                "evening": { "temp_min": -11, "temp_max": -9, "temp_avg": -10 }
             }
         }
     ]
@@ -81,20 +112,209 @@ Example response (simplified):
 
 #[derive(Debug, Deserialize)]
 struct ForecastResponse {
+    info: Option<Info>,
     forecasts: Vec<Forecast>,
 }
 
+#[derive(Debug, Deserialize)]
+struct Info {
+    tzinfo: Option<TzInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TzInfo {
+    /// Shift in seconds from UTC.
+    offset: i32,
+}
+
 #[derive(Debug, Deserialize)]
 struct Forecast {
+    date: NaiveDate,
     parts: Parts,
 }
 
 #[derive(Debug, Deserialize)]
 struct Parts {
-    day: Day,
+    night: Option<Part>,
+    morning: Option<Part>,
+    day: Part,
+    evening: Option<Part>,
+}
+
+impl Parts {
+    /// Iterates over all available parts of the day.
+    fn iter(&self) -> impl Iterator<Item = &Part> {
+        let parts = vec![
+            self.night.as_ref(),
+            self.morning.as_ref(),
+            Some(&self.day),
+            self.evening.as_ref(),
+        ];
+
+        parts.into_iter().flatten()
+    }
+
+    /// Builds a daily report. Instant values are taken from the daytime part,
+    /// while min/max temperatures and precipitation cover the whole day.
+    fn report(&self) -> Report {
+        let day = &self.day;
+        let precipitation = self.iter().filter_map(|p| p.prec_mm).collect::<Vec<_>>();
+
+        Report {
+            temperature_min: self
+                .iter()
+                .filter_map(|p| p.temp_min)
+                .min()
+                .map(|x| x as f32),
+            temperature_max: self
+                .iter()
+                .filter_map(|p| p.temp_max)
+                .max()
+                .map(|x| x as f32),
+            humidity: day.humidity,
+            pressure: day.pressure_pa,
+            wind_speed: day.wind_speed,
+            wind_direction: day.wind_dir.as_ref().and_then(|dir| wind_direction(dir)),
+            wind_gust: day.wind_gust,
+            precipitation: if precipitation.is_empty() {
+                None
+            } else {
+                Some(precipitation.iter().sum())
+            },
+            precipitation_probability: self
+                .iter()
+                .filter_map(|p| p.prec_prob)
+                .fold(None, |max: Option<f32>, x| {
+                    Some(max.map_or(x, |max| max.max(x)))
+                }),
+            cloud_cover: day.cloudness.map(|cloudness| cloudness * 100.0),
+            condition: day.condition.as_ref().and_then(|c| condition(c)),
+            ..Report::new(day.temp_avg as f32)
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
-struct Day {
+struct Part {
     temp_avg: isize,
+    temp_min: Option<isize>,
+    temp_max: Option<isize>,
+    /// %.
+    humidity: Option<f32>,
+    /// hPa.
+    pressure_pa: Option<f32>,
+    /// m/s.
+    wind_speed: Option<f32>,
+    /// m/s.
+    wind_gust: Option<f32>,
+    /// Compass point like "nw" or "c" for calm.
+    wind_dir: Option<String>,
+    /// mm.
+    prec_mm: Option<f32>,
+    /// %.
+    prec_prob: Option<f32>,
+    /// From 0 to 1.
+    cloudness: Option<f32>,
+    /// Code like "overcast-and-light-rain".
+    condition: Option<String>,
+}
+
+/// Converts a compass point to degrees. Returns `None` for calm or unknown values.
+fn wind_direction(compass_point: &str) -> Option<f32> {
+    let degrees = match compass_point {
+        "n" => 0.0,
+        "ne" => 45.0,
+        "e" => 90.0,
+        "se" => 135.0,
+        "s" => 180.0,
+        "sw" => 225.0,
+        "w" => 270.0,
+        "nw" => 315.0,
+        _ => return None,
+    };
+
+    Some(degrees)
+}
+
+/// Maps a Yandex condition code to the shared condition. Compound codes like
+/// "overcast-and-light-rain" are mapped by their precipitation part.
+/// Returns `None` for unknown codes.
+fn condition(code: &str) -> Option<Condition> {
+    let precipitation = code.rsplit("-and-").next().unwrap_or(code);
+
+    let condition = match precipitation {
+        "clear" => Condition::Clear,
+        "partly-cloudy" => Condition::PartlyCloudy,
+        "cloudy" => Condition::Cloudy,
+        "overcast" => Condition::Overcast,
+        "drizzle" => Condition::Drizzle,
+        "light-rain" => Condition::LightRain,
+        "rain" | "moderate-rain" => Condition::Rain,
+        "heavy-rain" | "continuous-heavy-rain" => Condition::HeavyRain,
+        "showers" => Condition::Showers,
+        "wet-snow" => Condition::Sleet,
+        "light-snow" => Condition::LightSnow,
+        "snow" => Condition::Snow,
+        "snow-showers" => Condition::SnowShowers,
+        "hail" => Condition::Hail,
+        "thunderstorm"
+        | "thunderstorm-with-rain"
+        | "thunderstorm-with-hail"
+        | "overcast-thunderstorms-with-rain" => Condition::Thunderstorm,
+        _ => return None,
+    };
+
+    Some(condition)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parts_report() {
+        let parts: Parts = serde_json::from_str(
+            r#"{
+                "night": { "temp_min": -15, "temp_max": -12, "temp_avg": -13, "prec_mm": 0.5 },
+                "day": {
+                    "temp_min": -12,
+                    "temp_max": -8,
+                    "temp_avg": -10,
+                    "humidity": 76,
+                    "wind_dir": "nw",
+                    "prec_mm": 1.25,
+                    "prec_prob": 40,
+                    "cloudness": 0.75,
+                    "condition": "overcast-and-light-snow"
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let report = parts.report();
+        assert_eq!(report.temperature, -10.0);
+        assert_eq!(report.temperature_min, Some(-15.0));
+        assert_eq!(report.temperature_max, Some(-8.0));
+        assert_eq!(report.humidity, Some(76.0));
+        assert_eq!(report.wind_direction, Some(315.0));
+        assert_eq!(report.precipitation, Some(1.75));
+        assert_eq!(report.precipitation_probability, Some(40.0));
+        assert_eq!(report.cloud_cover, Some(75.0));
+        assert_eq!(report.condition, Some(Condition::LightSnow));
+    }
+
+    #[test]
+    fn condition_mapping() {
+        assert_eq!(condition("partly-cloudy"), Some(Condition::PartlyCloudy));
+        assert_eq!(condition("cloudy-and-rain"), Some(Condition::Rain));
+        assert_eq!(
+            condition("thunderstorm-with-hail"),
+            Some(Condition::Thunderstorm)
+        );
+        assert_eq!(
+            condition("overcast-thunderstorms-with-rain"),
+            Some(Condition::Thunderstorm)
+        );
+        assert_eq!(condition("volcano"), None);
+    }
 }
@@ -1,65 +1,571 @@
+mod aggregation;
+mod condition;
 mod reducer;
+mod worker_pool;
 
+use std::collections::BTreeMap;
 use std::error::Error;
+use std::fmt;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
 
-use futures::{future, Future};
+use chrono::prelude::*;
+use lazy_static::lazy_static;
 use serde::Serialize;
+use simple_error::SimpleError;
 
+use super::config::CONFIG;
 use super::geocoder::GEOCODER;
-use super::provider::{Provider, PROVIDERS};
+use super::http;
+use super::provider::{estimated_utc_offset, ProviderEntry, ProviderError, PROVIDERS};
 use reducer::Reducer;
+use worker_pool::WorkerPool;
+
+pub use aggregation::{circular_mean, AggregationStrategy, Sample};
+pub use condition::Condition;
+
+lazy_static! {
+    // Threads calling providers, shared by all requests to bound the number of calls.
+    static ref WORKERS: WorkerPool =
+        CONFIG.with(|config| WorkerPool::new(config.borrow().reporter.workers));
+}
 
 #[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct Report {
-    temperature: f32,
+    /// Average daytime temperature, °C.
+    pub temperature: f32,
+    /// Minimal temperature over the day, °C.
+    pub temperature_min: Option<f32>,
+    /// Maximal temperature over the day, °C.
+    pub temperature_max: Option<f32>,
+    /// Relative humidity, %.
+    pub humidity: Option<f32>,
+    /// Atmospheric pressure at sea level, hPa.
+    pub pressure: Option<f32>,
+    /// Wind speed, m/s.
+    pub wind_speed: Option<f32>,
+    /// Direction the wind blows from, degrees clockwise from the north.
+    pub wind_direction: Option<f32>,
+    /// Speed of wind gusts, m/s.
+    pub wind_gust: Option<f32>,
+    /// Total precipitation amount over the day, mm.
+    pub precipitation: Option<f32>,
+    /// Probability of precipitation, %.
+    pub precipitation_probability: Option<f32>,
+    /// Cloud cover, %.
+    pub cloud_cover: Option<f32>,
+    /// Prevailing weather condition.
+    pub condition: Option<Condition>,
 }
 
 impl Report {
     pub fn new(temperature: f32) -> Self {
-        Self { temperature }
+        Self {
+            temperature,
+            temperature_min: None,
+            temperature_max: None,
+            humidity: None,
+            pressure: None,
+            wind_speed: None,
+            wind_direction: None,
+            wind_gust: None,
+            precipitation: None,
+            precipitation_probability: None,
+            cloud_cover: None,
+            condition: None,
+        }
+    }
+}
+
+/// A provider's report for a single calendar day in the location's time zone.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DailyForecast {
+    pub date: NaiveDate,
+    /// Time range the report is based on when the provider tells it.
+    pub period: Option<(DateTime<Utc>, DateTime<Utc>)>,
+    pub report: Report,
+}
+
+impl DailyForecast {
+    pub fn new(date: NaiveDate, report: Report) -> Self {
+        Self {
+            date,
+            period: None,
+            report,
+        }
+    }
+}
+
+/// A provider's forecasts along with the UTC offset of the location's time zone
+/// when the provider tells it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProviderForecast {
+    pub forecasts: Vec<DailyForecast>,
+    pub utc_offset: Option<FixedOffset>,
+}
+
+impl ProviderForecast {
+    pub fn new(forecasts: Vec<DailyForecast>) -> Self {
+        Self {
+            forecasts,
+            utc_offset: None,
+        }
+    }
+}
+
+/// An aggregated report for a single day.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DailyReport {
+    pub date: NaiveDate,
+    #[serde(flatten)]
+    pub report: Report,
+    /// Effective share of each contributing provider in the aggregated report.
+    pub weights: BTreeMap<String, f32>,
+    pub spread: Spread,
+    /// Raw reports of contributing providers keyed by provider name when requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub breakdown: Option<BTreeMap<String, Report>>,
+}
+
+/// Agreement of providers on the day's temperature.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Spread {
+    /// Number of contributing providers.
+    pub providers: usize,
+    /// Lowest provider's temperature, °C.
+    pub min: f32,
+    /// Highest provider's temperature, °C.
+    pub max: f32,
+    /// Standard deviation of providers' temperatures, °C.
+    pub stddev: f32,
+    /// From 0 (unreliable) to 1. Drops as providers disagree or fail to report.
+    pub confidence: f32,
+}
+
+/// Per-request reporting options.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Overrides the aggregation strategy from the config.
+    pub aggregation: Option<AggregationStrategy>,
+    /// Keeps raw reports of each provider in daily reports.
+    pub breakdown: bool,
+    /// Time to wait for providers. Can only shorten the deadline from the config.
+    pub deadline: Option<Duration>,
+}
+
+/// Outcome of a provider call.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceStatus {
+    Ok,
+    Timeout,
+    HttpError,
+    ParseError,
+    ProcessError,
+    IoError,
+    CircuitOpen,
+    Throttled,
+    /// The provider doesn't cover the location.
+    OutOfCoverage,
+}
+
+impl From<&ProviderError> for SourceStatus {
+    fn from(error: &ProviderError) -> Self {
+        match error {
+            ProviderError::Timeout(_) => SourceStatus::Timeout,
+            ProviderError::Http(_) | ProviderError::KeyRejected(_) => SourceStatus::HttpError,
+            ProviderError::Parse(_) => SourceStatus::ParseError,
+            ProviderError::Process(_) => SourceStatus::ProcessError,
+            ProviderError::Io(_) => SourceStatus::IoError,
+            ProviderError::CircuitOpen => SourceStatus::CircuitOpen,
+            ProviderError::Throttled | ProviderError::Busy => SourceStatus::Throttled,
+            ProviderError::OutOfCoverage => SourceStatus::OutOfCoverage,
+        }
     }
 }
 
-/// Asks each provider for a report on given number of days in a specific location.
-/// Returns a vector with averaged reports for each day.
-/// The location is being geocoded by geocoder provider at first.
-pub fn report(location: &str, days: usize) -> Result<Vec<Report>, Box<dyn Error>> {
-    let (lat, lon) = GEOCODER.with(|g| g.borrow().geocode(location))?;
-    let mut reducer = Reducer::new(days);
+/// Metadata of a configured provider's call made for the forecast.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Source {
+    pub provider: String,
+    pub status: SourceStatus,
+    /// Time taken by the call including retries, ms.
+    pub latency_ms: u64,
+    /// Number of retries of transient failures.
+    pub retries: u32,
+    /// Error message when the call failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Aggregated reports along with the providers they are based on.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Forecast {
+    /// Current date in the location's time zone the reports start from.
+    #[serde(skip)]
+    pub today: NaiveDate,
+    pub reports: Vec<DailyReport>,
+    pub sources: Vec<Source>,
+    /// Number of providers required for a day to be reported.
+    #[serde(skip)]
+    pub min_providers: usize,
+    /// Days left out for missing the quorum along with the number of providers that reported them.
+    #[serde(skip)]
+    pub short_days: BTreeMap<NaiveDate, usize>,
+}
+
+impl Forecast {
+    /// Takes the report for the date along with the sources. Fails with `QuorumError`
+    /// when fewer than `min_providers` providers reported the day.
+    pub fn into_report(self, date: NaiveDate) -> Result<(DailyReport, Vec<Source>), QuorumError> {
+        match self.reports.into_iter().find(|report| report.date == date) {
+            Some(report) => Ok((report, self.sources)),
+            None => Err(QuorumError {
+                required: self.min_providers,
+                succeeded: self.short_days.get(&date).copied().unwrap_or(0),
+                date: Some(date),
+                sources: self.sources,
+            }),
+        }
+    }
+}
 
-    PROVIDERS.with(|providers| {
-        let mut futures = Vec::with_capacity(providers.borrow().len());
+/// Fewer providers than required by `min_providers` have responded or reported the day.
+#[derive(Debug)]
+pub struct QuorumError {
+    pub required: usize,
+    pub succeeded: usize,
+    /// Day missing the quorum, `None` when the whole forecast misses it.
+    pub date: Option<NaiveDate>,
+    pub sources: Vec<Source>,
+}
 
-        // Call each provider asynchronously.
-        for provider in providers.borrow().iter() {
-            futures.push(get_provider_reports_async(&provider, lat, lon, days));
+impl fmt::Display for QuorumError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.date {
+            Some(date) => write!(
+                f,
+                "Only {} of {} required providers reported {}",
+                self.succeeded, self.required, date
+            ),
+            None => write!(
+                f,
+                "Only {} of {} required providers responded",
+                self.succeeded, self.required
+            ),
         }
+    }
+}
+
+impl Error for QuorumError {}
 
-        // Synchronize providers and ignore errors.
-        for provider_reports in futures.iter_mut().filter_map(|future| future.wait().ok()) {
-            reducer.push_provider_reports(&provider_reports);
+/// The location couldn't be geocoded in time.
+#[derive(Debug)]
+pub enum GeocodingError {
+    /// The geocoder didn't respond before the deadline.
+    Timeout,
+    /// All workers are busy.
+    Busy,
+}
+
+impl fmt::Display for GeocodingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GeocodingError::Timeout => write!(f, "Geocoding didn't finish before the deadline"),
+            GeocodingError::Busy => write!(f, "All workers are busy"),
         }
+    }
+}
+
+impl Error for GeocodingError {}
+
+/// Asks each provider for a report on given number of days starting from today
+/// in a specific location. Today is the current date in the location's time zone.
+/// Returns aggregated reports for each day and the outcome of each provider's call.
+/// The location is being geocoded by geocoder provider at first, within the same deadline.
+/// Fails with `QuorumError` when fewer than `min_providers` providers respond. Days reported
+/// by fewer than `min_providers` providers are left out and listed in `short_days`.
+/// Providers that don't respond before the deadline are reported as timed out and left
+/// running in the background. When all workers are busy, providers are reported as throttled.
+pub fn report(location: &str, days: usize, options: &Options) -> Result<Forecast, Box<dyn Error>> {
+    let started_at = Instant::now();
+
+    let (default_strategy, min_providers, max_deadline) = CONFIG.with(|config| {
+        let reporter = &config.borrow().reporter;
+        let deadline = Duration::from_millis(reporter.deadline_ms);
+        (
+            reporter.aggregation,
+            reporter.min_providers.max(1),
+            deadline,
+        )
     });
 
-    Ok(reducer.reduce_reports())
+    let deadline = started_at
+        + options
+            .deadline
+            .map_or(max_deadline, |d| d.min(max_deadline));
+    let (lat, lon) = geocode(location, deadline)?;
+
+    let strategy = options.aggregation.unwrap_or(default_strategy);
+
+    let providers: &'static [ProviderEntry] = &PROVIDERS;
+    let mut results = (0..providers.len()).map(|_| None).collect::<Vec<_>>();
+    let mut pending = 0;
+
+    // Call all providers concurrently, each one on its own worker.
+    // Providers with open circuit breakers or spent quotas are skipped.
+    let (sender, receiver) = mpsc::channel();
+    let providers_started_at = Instant::now();
+
+    for (index, entry) in providers.iter().enumerate() {
+        let worker = match WORKERS.try_reserve() {
+            Some(worker) => worker,
+            None => {
+                results[index] = Some((Err(ProviderError::Busy), Duration::default(), 0));
+                continue;
+            }
+        };
+
+        let permit = match entry.circuit_breaker.try_acquire() {
+            Some(permit) => permit,
+            None => {
+                results[index] = Some((Err(ProviderError::CircuitOpen), Duration::default(), 0));
+                continue;
+            }
+        };
+
+        // All keys are quarantined or out of quota.
+        let key = match entry.keys.acquire() {
+            Some(key) => key,
+            None => {
+                permit.release();
+                results[index] = Some((Err(ProviderError::Throttled), Duration::default(), 0));
+                continue;
+            }
+        };
+
+        let sender = sender.clone();
+        pending += 1;
+
+        worker.execute(move || {
+            let started_at = Instant::now();
+            let (result, retries) =
+                http::with_retries(|| get_provider_reports(entry, key, lat, lon, days));
+            permit.record(&result);
+            let call = (result, started_at.elapsed(), retries);
+            // Fails when the deadline has passed and nobody waits for the result anymore.
+            sender.send((index, call)).ok();
+        });
+    }
+
+    drop(sender);
+
+    // Wait for providers until the deadline.
+
+    while pending > 0 {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+
+        match receiver.recv_timeout(timeout) {
+            Ok((index, call)) => {
+                results[index] = Some(call);
+                pending -= 1;
+            }
+            Err(_) => break,
+        }
+    }
+
+    // Days are counted from today in the location's time zone like providers' dates.
+    // The offset is taken from the first provider telling it or estimated by the longitude.
+    let utc_offset = results
+        .iter()
+        .filter_map(|result| match result {
+            Some((Ok(forecast), _, _)) => forecast.utc_offset,
+            _ => None,
+        })
+        .next()
+        .unwrap_or_else(|| estimated_utc_offset(lon));
+    let today = Utc::now().with_timezone(&utc_offset).naive_local().date();
+
+    // Providers that don't cover the location don't count in the confidence.
+    let covering = results
+        .iter()
+        .filter(|result| !matches!(result, Some((Err(ProviderError::OutOfCoverage), _, _))))
+        .count();
+    let mut reducer = Reducer::new(today, days, strategy, covering);
+
+    // Handle providers in the config order to keep results stable.
+    let mut sources = Vec::with_capacity(providers.len());
+
+    for (entry, result) in providers.iter().zip(results) {
+        let name = entry.provider.name();
+
+        // Retries of a provider that missed the deadline are unknown.
+        let (result, latency, retries) = result.unwrap_or_else(|| {
+            let error = ProviderError::Timeout(String::from("Deadline exceeded"));
+            let latency = deadline.saturating_duration_since(providers_started_at);
+            (Err(error), latency, 0)
+        });
+
+        let latency_ms = latency.as_millis() as u64;
+
+        let source = match result {
+            Ok(provider_forecast) => {
+                reducer.push_provider_reports(&name, entry.weight, &provider_forecast.forecasts);
+
+                Source {
+                    provider: name,
+                    status: SourceStatus::Ok,
+                    latency_ms,
+                    retries,
+                    error: None,
+                }
+            }
+            Err(error) => Source {
+                provider: name,
+                status: SourceStatus::from(&error),
+                latency_ms,
+                retries,
+                error: Some(error.to_string()),
+            },
+        };
+
+        sources.push(source);
+    }
+
+    let sources = check_quorum(sources, min_providers)?;
+    let (mut reports, short_reports): (Vec<_>, Vec<_>) = reducer
+        .reduce_reports()
+        .into_iter()
+        .partition(|report| report.spread.providers >= min_providers);
+
+    let short_days = short_reports
+        .iter()
+        .map(|report| (report.date, report.spread.providers))
+        .collect();
+
+    if !options.breakdown {
+        for report in &mut reports {
+            report.breakdown = None;
+        }
+    }
+
+    Ok(Forecast {
+        today,
+        reports,
+        sources,
+        min_providers,
+        short_days,
+    })
+}
+
+/// Passes `sources` through when at least `min_providers` of them succeeded.
+fn check_quorum(sources: Vec<Source>, min_providers: usize) -> Result<Vec<Source>, QuorumError> {
+    let succeeded = sources
+        .iter()
+        .filter(|source| source.status == SourceStatus::Ok)
+        .count();
+
+    if succeeded < min_providers {
+        Err(QuorumError {
+            required: min_providers,
+            succeeded,
+            date: None,
+            sources,
+        })
+    } else {
+        Ok(sources)
+    }
 }
 
-/// Wraps provider report call into future.
-fn get_provider_reports_async(
-    provider: &Box<dyn Provider>,
+/// Geocodes the location on a worker to stop waiting for it at the deadline.
+/// Fails with `GeocodingError` when the geocoder misses the deadline or there is no free worker.
+fn geocode(location: &str, deadline: Instant) -> Result<(f32, f32), Box<dyn Error>> {
+    let worker = WORKERS.try_reserve().ok_or(GeocodingError::Busy)?;
+    let (sender, receiver) = mpsc::channel();
+    let location = String::from(location);
+
+    worker.execute(move || {
+        // Geocoder retries aren't reported, they mustn't count for providers on the worker.
+        let (result, _) = http::with_retries(|| GEOCODER.geocode(&location));
+        // Fails when the deadline has passed and nobody waits for the result anymore.
+        sender.send(result.map_err(|error| error.to_string())).ok();
+    });
+
+    let timeout = deadline.saturating_duration_since(Instant::now());
+
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result.map_err(|message| SimpleError::new(message).into()),
+        Err(RecvTimeoutError::Timeout) => Err(GeocodingError::Timeout.into()),
+        Err(RecvTimeoutError::Disconnected) => bail!("Failed to geocode location"),
+    }
+}
+
+/// Calls the provider and logs the failure if any.
+fn get_provider_reports(
+    entry: &ProviderEntry,
+    key: usize,
     lat: f32,
     lon: f32,
     days: usize,
-) -> impl Future<Item = Vec<Report>, Error = Box<dyn 'static + Error>> {
-    match provider.get_reports(lat, lon, days) {
-        Ok(reports) => future::ok(reports),
-        Err(error) => {
+) -> Result<ProviderForecast, ProviderError> {
+    entry.get_reports(key, lat, lon, days).map_err(|error| {
+        if error.is_failure() {
             println!(
                 "Error fetching from provider {}: {}",
-                provider.name(),
+                entry.provider.name(),
                 error
             );
-            future::err(error)
         }
+        error
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(status: SourceStatus) -> Source {
+        Source {
+            provider: String::from("provider"),
+            status,
+            latency_ms: 0,
+            retries: 0,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn check_quorum() {
+        let sources = vec![source(SourceStatus::Ok), source(SourceStatus::Timeout)];
+        assert!(super::check_quorum(sources.clone(), 1).is_ok());
+
+        let error = super::check_quorum(sources, 2).unwrap_err();
+        assert_eq!(error.required, 2);
+        assert_eq!(error.succeeded, 1);
+        assert_eq!(error.sources.len(), 2);
+    }
+
+    #[test]
+    fn into_report() {
+        let date = NaiveDate::from_ymd(2019, 3, 11);
+        let forecast = |short_days: &[(NaiveDate, usize)]| Forecast {
+            today: date,
+            reports: vec![],
+            sources: vec![source(SourceStatus::Ok), source(SourceStatus::Ok)],
+            min_providers: 2,
+            short_days: short_days.iter().copied().collect(),
+        };
+
+        let error = forecast(&[(date, 1)]).into_report(date).unwrap_err();
+        assert_eq!(error.succeeded, 1);
+        assert_eq!(error.sources.len(), 2);
+        assert_eq!(
+            error.to_string(),
+            "Only 1 of 2 required providers reported 2019-03-11"
+        );
+
+        // Nobody reported the day.
+        let error = forecast(&[]).into_report(date).unwrap_err();
+        assert_eq!(error.succeeded, 0);
     }
 }
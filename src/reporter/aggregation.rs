@@ -0,0 +1,245 @@
+use std::cmp::Ordering;
+
+use serde::Deserialize;
+
+// Share of samples to drop from each side for the trimmed mean.
+const TRIM_RATIO: f32 = 0.2;
+
+/// The way reports of different providers for the same day are combined into a single value.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AggregationStrategy {
+    /// Arithmetic mean.
+    #[default]
+    Mean,
+    /// Middle value; the mean of the two middle values for an even number of samples.
+    Median,
+    /// Mean after dropping the lowest and the highest 20% of samples rounded down,
+    /// so fewer than 5 samples aren't trimmed.
+    TrimmedMean,
+    /// Mean weighted by provider weights.
+    WeightedMean,
+    /// Middle of the min/max range. Lower bounds (like minimal temperature) take the lowest
+    /// value and upper bounds take the highest one so the result covers all providers.
+    Envelope,
+}
+
+/// A value contributed by a single provider.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sample {
+    pub value: f32,
+    pub weight: f32,
+}
+
+impl Sample {
+    pub fn new(value: f32, weight: f32) -> Self {
+        Self { value, weight }
+    }
+}
+
+impl AggregationStrategy {
+    /// Combines `samples` into a single value. Returns `None` when there are no samples.
+    pub fn aggregate(self, samples: &[Sample]) -> Option<f32> {
+        if samples.is_empty() {
+            return None;
+        }
+
+        let value = match self {
+            AggregationStrategy::Mean => mean(samples),
+            AggregationStrategy::Median => median(samples),
+            AggregationStrategy::TrimmedMean => trimmed_mean(samples),
+            AggregationStrategy::WeightedMean => weighted_mean(samples),
+            AggregationStrategy::Envelope => (min(samples) + max(samples)) / 2.0,
+        };
+
+        Some(value)
+    }
+
+    /// Returns the effective share of each sample in the aggregated value.
+    /// Only `weighted_mean` takes weights into account, other strategies treat samples equally.
+    pub fn effective_weights(self, samples: &[Sample]) -> Vec<f32> {
+        let total_weight = samples.iter().map(|s| s.weight).sum::<f32>();
+
+        match self {
+            AggregationStrategy::WeightedMean if total_weight > 0.0 => {
+                samples.iter().map(|s| s.weight / total_weight).collect()
+            }
+            _ => vec![1.0 / samples.len() as f32; samples.len()],
+        }
+    }
+
+    /// Combines lower bound `samples`, e.g. minimal temperatures.
+    pub fn aggregate_lower(self, samples: &[Sample]) -> Option<f32> {
+        match self {
+            AggregationStrategy::Envelope if !samples.is_empty() => Some(min(samples)),
+            _ => self.aggregate(samples),
+        }
+    }
+
+    /// Combines upper bound `samples`, e.g. maximal temperatures.
+    pub fn aggregate_upper(self, samples: &[Sample]) -> Option<f32> {
+        match self {
+            AggregationStrategy::Envelope if !samples.is_empty() => Some(max(samples)),
+            _ => self.aggregate(samples),
+        }
+    }
+}
+
+/// Weighted mean of angles in degrees, e.g. wind direction. Returns a value in `[0, 360)`
+/// or `None` when there are no samples or they cancel each other out.
+pub fn circular_mean(samples: &[Sample]) -> Option<f32> {
+    let (x, y) = samples.iter().fold((0.0, 0.0), |(x, y), s| {
+        let angle = s.value.to_radians();
+        (x + angle.cos() * s.weight, y + angle.sin() * s.weight)
+    });
+
+    if x.abs() < f32::EPSILON && y.abs() < f32::EPSILON {
+        None
+    } else {
+        Some((y.atan2(x).to_degrees() + 360.0) % 360.0)
+    }
+}
+
+fn mean(samples: &[Sample]) -> f32 {
+    samples.iter().map(|s| s.value).sum::<f32>() / samples.len() as f32
+}
+
+fn median(samples: &[Sample]) -> f32 {
+    let values = sorted_values(samples);
+    let middle = values.len() / 2;
+
+    if values.len().is_multiple_of(2) {
+        (values[middle - 1] + values[middle]) / 2.0
+    } else {
+        values[middle]
+    }
+}
+
+fn trimmed_mean(samples: &[Sample]) -> f32 {
+    let values = sorted_values(samples);
+    let trim = trim_count(values.len());
+    let kept = &values[trim..values.len() - trim];
+    kept.iter().sum::<f32>() / kept.len() as f32
+}
+
+/// Number of samples to drop from each side for the trimmed mean of `len` samples.
+fn trim_count(len: usize) -> usize {
+    (len as f32 * TRIM_RATIO).floor() as usize
+}
+
+fn weighted_mean(samples: &[Sample]) -> f32 {
+    let total_weight = samples.iter().map(|s| s.weight).sum::<f32>();
+
+    if total_weight > 0.0 {
+        samples.iter().map(|s| s.value * s.weight).sum::<f32>() / total_weight
+    } else {
+        mean(samples)
+    }
+}
+
+fn min(samples: &[Sample]) -> f32 {
+    samples
+        .iter()
+        .map(|s| s.value)
+        .fold(f32::INFINITY, f32::min)
+}
+
+fn max(samples: &[Sample]) -> f32 {
+    samples
+        .iter()
+        .map(|s| s.value)
+        .fold(f32::NEG_INFINITY, f32::max)
+}
+
+fn sorted_values(samples: &[Sample]) -> Vec<f32> {
+    let mut values = samples.iter().map(|s| s.value).collect::<Vec<_>>();
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(values: &[f32]) -> Vec<Sample> {
+        values
+            .iter()
+            .map(|value| Sample::new(*value, 1.0))
+            .collect()
+    }
+
+    #[test]
+    fn mean() {
+        let result = AggregationStrategy::Mean.aggregate(&samples(&[1.0, 2.0, 6.0]));
+        assert_eq!(result, Some(3.0));
+    }
+
+    #[test]
+    fn median() {
+        let strategy = AggregationStrategy::Median;
+        assert_eq!(strategy.aggregate(&samples(&[6.0, 1.0, 2.0])), Some(2.0));
+        assert_eq!(
+            strategy.aggregate(&samples(&[6.0, 1.0, 2.0, 3.0])),
+            Some(2.5)
+        );
+    }
+
+    #[test]
+    fn trimmed_mean() {
+        assert_eq!(trim_count(2), 0);
+        assert_eq!(trim_count(3), 0);
+        assert_eq!(trim_count(4), 0);
+        assert_eq!(trim_count(5), 1);
+        assert_eq!(trim_count(10), 2);
+
+        let strategy = AggregationStrategy::TrimmedMean;
+        assert_eq!(strategy.aggregate(&samples(&[1.0, 3.0])), Some(2.0));
+        assert_eq!(strategy.aggregate(&samples(&[1.0, 2.0, 6.0])), Some(3.0));
+        assert_eq!(
+            strategy.aggregate(&samples(&[1.0, 2.0, 3.0, 10.0])),
+            Some(4.0)
+        );
+        assert_eq!(
+            strategy.aggregate(&samples(&[-20.0, 1.0, 2.0, 3.0, 40.0])),
+            Some(2.0)
+        );
+    }
+
+    #[test]
+    fn weighted_mean() {
+        let strategy = AggregationStrategy::WeightedMean;
+        let samples = vec![Sample::new(1.0, 3.0), Sample::new(5.0, 1.0)];
+        assert_eq!(strategy.aggregate(&samples), Some(2.0));
+    }
+
+    #[test]
+    fn effective_weights() {
+        let samples = vec![Sample::new(1.0, 3.0), Sample::new(5.0, 1.0)];
+        let weighted = AggregationStrategy::WeightedMean.effective_weights(&samples);
+        assert_eq!(weighted, vec![0.75, 0.25]);
+        let equal = AggregationStrategy::Median.effective_weights(&samples);
+        assert_eq!(equal, vec![0.5, 0.5]);
+    }
+
+    #[test]
+    fn envelope() {
+        let strategy = AggregationStrategy::Envelope;
+        let samples = samples(&[-1.0, 2.0, 5.0]);
+        assert_eq!(strategy.aggregate(&samples), Some(2.0));
+        assert_eq!(strategy.aggregate_lower(&samples), Some(-1.0));
+        assert_eq!(strategy.aggregate_upper(&samples), Some(5.0));
+    }
+
+    #[test]
+    fn circular_mean_over_north() {
+        let result = circular_mean(&samples(&[350.0, 30.0])).unwrap();
+        assert!((result - 10.0).abs() < 0.001);
+        assert_eq!(circular_mean(&samples(&[90.0, 270.0])), None);
+    }
+
+    #[test]
+    fn no_samples() {
+        assert_eq!(AggregationStrategy::Median.aggregate(&[]), None);
+        assert_eq!(AggregationStrategy::Envelope.aggregate_lower(&[]), None);
+    }
+}
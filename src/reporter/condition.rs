@@ -0,0 +1,172 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+
+/// Weather condition shared by all providers. Each provider maps its own codes to it.
+///
+/// Variants are ordered by severity from the mildest to the most severe one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Condition {
+    Clear,
+    PartlyCloudy,
+    Cloudy,
+    Overcast,
+    Fog,
+    Drizzle,
+    LightRain,
+    Rain,
+    Showers,
+    HeavyRain,
+    LightSnow,
+    Snow,
+    SnowShowers,
+    HeavySnow,
+    Sleet,
+    Hail,
+    Thunderstorm,
+}
+
+impl Condition {
+    const ALL: [Condition; 17] = [
+        Condition::Clear,
+        Condition::PartlyCloudy,
+        Condition::Cloudy,
+        Condition::Overcast,
+        Condition::Fog,
+        Condition::Drizzle,
+        Condition::LightRain,
+        Condition::Rain,
+        Condition::Showers,
+        Condition::HeavyRain,
+        Condition::LightSnow,
+        Condition::Snow,
+        Condition::SnowShowers,
+        Condition::HeavySnow,
+        Condition::Sleet,
+        Condition::Hail,
+        Condition::Thunderstorm,
+    ];
+
+    /// Looks a condition up by its `code`.
+    pub fn from_code(code: &str) -> Option<Condition> {
+        Condition::ALL
+            .iter()
+            .copied()
+            .find(|condition| condition.code() == code)
+    }
+
+    /// Stable machine-readable code, e.g. `light_rain`.
+    pub fn code(self) -> &'static str {
+        match self {
+            Condition::Clear => "clear",
+            Condition::PartlyCloudy => "partly_cloudy",
+            Condition::Cloudy => "cloudy",
+            Condition::Overcast => "overcast",
+            Condition::Fog => "fog",
+            Condition::Drizzle => "drizzle",
+            Condition::LightRain => "light_rain",
+            Condition::Rain => "rain",
+            Condition::Showers => "showers",
+            Condition::HeavyRain => "heavy_rain",
+            Condition::LightSnow => "light_snow",
+            Condition::Snow => "snow",
+            Condition::SnowShowers => "snow_showers",
+            Condition::HeavySnow => "heavy_snow",
+            Condition::Sleet => "sleet",
+            Condition::Hail => "hail",
+            Condition::Thunderstorm => "thunderstorm",
+        }
+    }
+
+    /// Human-readable label, e.g. "Light rain".
+    pub fn label(self) -> &'static str {
+        match self {
+            Condition::Clear => "Clear",
+            Condition::PartlyCloudy => "Partly cloudy",
+            Condition::Cloudy => "Cloudy",
+            Condition::Overcast => "Overcast",
+            Condition::Fog => "Fog",
+            Condition::Drizzle => "Drizzle",
+            Condition::LightRain => "Light rain",
+            Condition::Rain => "Rain",
+            Condition::Showers => "Showers",
+            Condition::HeavyRain => "Heavy rain",
+            Condition::LightSnow => "Light snow",
+            Condition::Snow => "Snow",
+            Condition::SnowShowers => "Snow showers",
+            Condition::HeavySnow => "Heavy snow",
+            Condition::Sleet => "Sleet",
+            Condition::Hail => "Hail",
+            Condition::Thunderstorm => "Thunderstorm",
+        }
+    }
+
+    /// Picks the condition with the highest total weight of votes.
+    /// Ties are resolved in favour of the more severe condition.
+    pub fn consensus<I>(votes: I) -> Option<Condition>
+    where
+        I: IntoIterator<Item = (Condition, f32)>,
+    {
+        let mut totals: HashMap<Condition, f32> = HashMap::new();
+
+        for (condition, weight) in votes {
+            *totals.entry(condition).or_insert(0.0) += weight;
+        }
+
+        totals
+            .into_iter()
+            .max_by(|(a, a_weight), (b, b_weight)| {
+                a_weight
+                    .partial_cmp(b_weight)
+                    .unwrap_or(Ordering::Equal)
+                    .then_with(|| a.cmp(b))
+            })
+            .map(|(condition, _)| condition)
+    }
+}
+
+/// Serializes as `{"code": "light_rain", "label": "Light rain"}`.
+impl Serialize for Condition {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Condition", 2)?;
+        state.serialize_field("code", self.code())?;
+        state.serialize_field("label", self.label())?;
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn consensus() {
+        let votes = vec![
+            (Condition::Rain, 3.0),
+            (Condition::Cloudy, 1.0),
+            (Condition::Cloudy, 1.0),
+        ];
+        assert_eq!(Condition::consensus(votes), Some(Condition::Rain));
+
+        let tie = vec![(Condition::Snow, 1.0), (Condition::Overcast, 1.0)];
+        assert_eq!(Condition::consensus(tie), Some(Condition::Snow));
+
+        assert_eq!(Condition::consensus(vec![]), None);
+    }
+
+    #[test]
+    fn from_code() {
+        for condition in Condition::ALL.iter() {
+            assert_eq!(Condition::from_code(condition.code()), Some(*condition));
+        }
+
+        assert_eq!(Condition::from_code("volcano"), None);
+    }
+
+    #[test]
+    fn serialize() {
+        let json = serde_json::to_string(&Condition::LightRain).unwrap();
+        assert_eq!(json, r#"{"code":"light_rain","label":"Light rain"}"#);
+    }
+}
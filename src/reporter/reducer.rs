@@ -1,52 +1,344 @@
-use super::Report;
+use std::collections::{BTreeMap, HashSet};
+
+use chrono::{Duration, NaiveDate};
+
+use super::aggregation::{circular_mean, AggregationStrategy, Sample};
+use super::{Condition, DailyForecast, DailyReport, Report, Spread};
+
+// Standard deviation of providers' temperatures at which the confidence drops to zero, °C.
+const CONFIDENCE_MAX_STDDEV: f32 = 5.0;
 
 pub struct Reducer {
-    reports: Vec<Vec<Report>>,
+    reports: BTreeMap<NaiveDate, Vec<Contribution>>,
+    strategy: AggregationStrategy,
+    providers_count: usize,
+}
+
+/// A report of a single provider for a day.
+struct Contribution {
+    provider: String,
+    weight: f32,
+    report: Report,
 }
 
 impl Reducer {
-    pub fn new(days: usize) -> Self {
+    /// Creates a reducer for `days` days starting from `start` date which combines
+    /// reports of `providers_count` providers with the given aggregation `strategy`.
+    pub fn new(
+        start: NaiveDate,
+        days: usize,
+        strategy: AggregationStrategy,
+        providers_count: usize,
+    ) -> Self {
+        let reports = (0..days as i64)
+            .map(|day| (start + Duration::days(day), vec![]))
+            .collect();
+
         Self {
-            reports: vec![vec![]; days],
+            reports,
+            strategy,
+            providers_count,
         }
     }
 
-    /// Gets a vector of reports for the number of days by single provider and spreads them
-    /// by days in the inner state for future reduction.
-    pub fn push_provider_reports(&mut self, provider_reports: &Vec<Report>) {
-        for (report, day_reports) in provider_reports.iter().zip(self.reports.iter_mut()) {
-            day_reports.push(report.clone());
+    /// Gets forecasts by single provider with its `weight` and groups them by date
+    /// in the inner state for future reduction. Forecasts for dates out of the range
+    /// are ignored as well as repeated forecasts for the same date.
+    pub fn push_provider_reports(
+        &mut self,
+        provider: &str,
+        weight: f32,
+        provider_reports: &[DailyForecast],
+    ) {
+        let mut seen_dates = HashSet::new();
+
+        for forecast in provider_reports {
+            if !seen_dates.insert(forecast.date) {
+                continue;
+            }
+
+            if let Some(day_reports) = self.reports.get_mut(&forecast.date) {
+                day_reports.push(Contribution {
+                    provider: String::from(provider),
+                    weight,
+                    report: forecast.report.clone(),
+                });
+            }
         }
     }
 
-    /// Gets reports for each day for each provider and returns averaged reports for each day.
-    pub fn reduce_reports(&self) -> Vec<Report> {
+    /// Gets reports for each day for each provider and returns aggregated reports for each day
+    /// along with the raw reports of each provider.
+    /// Each field is aggregated over the providers that reported it. Wind direction is
+    /// always a circular mean and the condition is picked by a weighted vote with ties
+    /// resolved in favour of the more severe condition.
+    /// Days without any reports are skipped.
+    pub fn reduce_reports(&self) -> Vec<DailyReport> {
         self.reports
             .iter()
-            .map(|day_reports| {
-                let temperature_sum = day_reports.iter().map(|r| r.temperature).sum::<f32>();
-                let mean_temperature = temperature_sum / self.reports.len() as f32;
-                Report::new(mean_temperature)
+            .filter_map(|(date, day_reports)| {
+                let strategy = self.strategy;
+                let temperature_samples = samples(day_reports, |r| Some(r.temperature));
+                let temperature = strategy.aggregate(&temperature_samples)?;
+
+                let weights = day_reports
+                    .iter()
+                    .map(|c| c.provider.clone())
+                    .zip(strategy.effective_weights(&temperature_samples))
+                    .collect();
+
+                let report = Report {
+                    temperature_min: strategy
+                        .aggregate_lower(&samples(day_reports, |r| r.temperature_min)),
+                    temperature_max: strategy
+                        .aggregate_upper(&samples(day_reports, |r| r.temperature_max)),
+                    humidity: strategy.aggregate(&samples(day_reports, |r| r.humidity)),
+                    pressure: strategy.aggregate(&samples(day_reports, |r| r.pressure)),
+                    wind_speed: strategy.aggregate(&samples(day_reports, |r| r.wind_speed)),
+                    wind_direction: circular_mean(&samples(day_reports, |r| r.wind_direction)),
+                    wind_gust: strategy.aggregate_upper(&samples(day_reports, |r| r.wind_gust)),
+                    precipitation: strategy.aggregate(&samples(day_reports, |r| r.precipitation)),
+                    precipitation_probability: strategy
+                        .aggregate(&samples(day_reports, |r| r.precipitation_probability)),
+                    cloud_cover: strategy.aggregate(&samples(day_reports, |r| r.cloud_cover)),
+                    condition: consensus_condition(day_reports),
+                    ..Report::new(temperature)
+                };
+
+                Some(DailyReport {
+                    date: *date,
+                    report,
+                    weights,
+                    spread: spread(&temperature_samples, self.providers_count),
+                    breakdown: Some(breakdown(day_reports)),
+                })
             })
             .collect()
     }
 }
 
+/// Collects weighted samples of the `field` from the reports that have it.
+fn samples<F>(contributions: &[Contribution], field: F) -> Vec<Sample>
+where
+    F: Fn(&Report) -> Option<f32>,
+{
+    contributions
+        .iter()
+        .filter_map(|c| field(&c.report).map(|value| Sample::new(value, c.weight)))
+        .collect()
+}
+
+/// Collects raw reports of each provider for the day.
+fn breakdown(contributions: &[Contribution]) -> BTreeMap<String, Report> {
+    contributions
+        .iter()
+        .map(|c| (c.provider.clone(), c.report.clone()))
+        .collect()
+}
+
+/// Describes how close providers' temperatures are to each other. Expects at least one sample.
+/// The confidence is the share of providers that reported the day scaled down linearly
+/// with the standard deviation: to zero at `CONFIDENCE_MAX_STDDEV`.
+fn spread(temperature_samples: &[Sample], providers_count: usize) -> Spread {
+    let values = temperature_samples
+        .iter()
+        .map(|s| s.value)
+        .collect::<Vec<_>>();
+
+    let n = values.len() as f32;
+    let mean = values.iter().sum::<f32>() / n;
+    let stddev = (values.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / n).sqrt();
+
+    let agreement = (1.0 - stddev / CONFIDENCE_MAX_STDDEV).max(0.0);
+    let coverage = n / providers_count.max(values.len()) as f32;
+
+    Spread {
+        providers: values.len(),
+        min: values.iter().cloned().fold(f32::INFINITY, f32::min),
+        max: values.iter().cloned().fold(f32::NEG_INFINITY, f32::max),
+        stddev,
+        confidence: agreement * coverage,
+    }
+}
+
+/// Picks the condition by a vote weighted with provider weights.
+fn consensus_condition(contributions: &[Contribution]) -> Option<Condition> {
+    Condition::consensus(
+        contributions
+            .iter()
+            .filter_map(|c| c.report.condition.map(|condition| (condition, c.weight))),
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::Report;
     use super::*;
 
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd(2019, 3, day)
+    }
+
+    fn forecast(day: u32, temperature: f32) -> DailyForecast {
+        DailyForecast::new(date(day), Report::new(temperature))
+    }
+
+    fn temperatures(reports: &[DailyReport]) -> Vec<(NaiveDate, f32)> {
+        reports
+            .iter()
+            .map(|r| (r.date, r.report.temperature))
+            .collect()
+    }
+
     #[test]
     fn reduce_reports() {
-        let provider1_reports = vec![Report::new(1.0), Report::new(2.0)];
-        let provider2_reports = vec![Report::new(3.0), Report::new(4.0)];
+        let provider1_reports = vec![forecast(11, 1.0), forecast(12, 2.0)];
+        let provider2_reports = vec![forecast(11, 3.0), forecast(12, 4.0)];
+
+        let mut reducer = Reducer::new(date(11), 2, AggregationStrategy::Mean, 2);
+        reducer.push_provider_reports("provider1", 1.0, &provider1_reports);
+        reducer.push_provider_reports("provider2", 1.0, &provider2_reports);
+        let reports = reducer.reduce_reports();
+
+        assert_eq!(
+            temperatures(&reports),
+            vec![(date(11), 2.0), (date(12), 3.0)]
+        );
+    }
 
-        let mut reducer = Reducer::new(2);
-        reducer.push_provider_reports(&provider1_reports);
-        reducer.push_provider_reports(&provider2_reports);
+    #[test]
+    fn reduce_reports_with_strategy() {
+        let provider1_reports = vec![forecast(11, 1.0)];
+        let provider2_reports = vec![forecast(11, 2.0)];
+        let provider3_reports = vec![forecast(11, 9.0)];
+
+        let mut reducer = Reducer::new(date(11), 1, AggregationStrategy::Median, 3);
+        reducer.push_provider_reports("provider1", 1.0, &provider1_reports);
+        reducer.push_provider_reports("provider2", 1.0, &provider2_reports);
+        reducer.push_provider_reports("provider3", 1.0, &provider3_reports);
+        let reports = reducer.reduce_reports();
+
+        assert_eq!(temperatures(&reports), vec![(date(11), 2.0)]);
+    }
+
+    #[test]
+    fn reduce_reports_with_weights() {
+        let provider1_reports = vec![forecast(11, 1.0), forecast(12, 2.0)];
+        let provider2_reports = vec![forecast(11, 5.0)];
+
+        let mut reducer = Reducer::new(date(11), 2, AggregationStrategy::WeightedMean, 2);
+        reducer.push_provider_reports("provider1", 3.0, &provider1_reports);
+        reducer.push_provider_reports("provider2", 1.0, &provider2_reports);
+        let reports = reducer.reduce_reports();
+
+        assert_eq!(
+            temperatures(&reports),
+            vec![(date(11), 2.0), (date(12), 2.0)]
+        );
+
+        let first_day_weights = vec![
+            (String::from("provider1"), 0.75),
+            (String::from("provider2"), 0.25),
+        ];
+        assert_eq!(reports[0].weights, first_day_weights.into_iter().collect());
+
+        let second_day_weights = vec![(String::from("provider1"), 1.0)];
+        assert_eq!(reports[1].weights, second_day_weights.into_iter().collect());
+
+        let first_day_breakdown = reports[0].breakdown.as_ref().unwrap();
+        assert_eq!(first_day_breakdown["provider1"], Report::new(1.0));
+        assert_eq!(first_day_breakdown["provider2"], Report::new(5.0));
+    }
+
+    #[test]
+    fn reduce_all_fields() {
+        let report1 = Report {
+            humidity: Some(80.0),
+            wind_direction: Some(350.0),
+            wind_gust: Some(10.0),
+            precipitation: Some(2.0),
+            condition: Some(Condition::Rain),
+            ..Report::new(1.0)
+        };
+
+        let report2 = Report {
+            humidity: Some(60.0),
+            wind_direction: Some(30.0),
+            wind_gust: Some(14.0),
+            condition: Some(Condition::Cloudy),
+            ..Report::new(3.0)
+        };
+
+        let report3 = Report {
+            condition: Some(Condition::Cloudy),
+            ..Report::new(5.0)
+        };
+
+        let mut reducer = Reducer::new(date(11), 1, AggregationStrategy::Envelope, 3);
+        reducer.push_provider_reports("provider1", 3.0, &[DailyForecast::new(date(11), report1)]);
+        reducer.push_provider_reports("provider2", 1.0, &[DailyForecast::new(date(11), report2)]);
+        reducer.push_provider_reports("provider3", 1.0, &[DailyForecast::new(date(11), report3)]);
+        let report = &reducer.reduce_reports()[0].report;
+
+        assert_eq!(report.temperature, 3.0);
+        assert_eq!(report.humidity, Some(70.0));
+        // 350° weighs more than 30° so the mean is just to the west of the north.
+        let wind_direction = report.wind_direction.unwrap();
+        assert!(wind_direction > 359.0 && wind_direction < 360.0);
+        assert_eq!(report.wind_gust, Some(14.0));
+        assert_eq!(report.precipitation, Some(2.0));
+        assert_eq!(report.pressure, None);
+        // "rain" has a weight of 3 against 2 for "cloudy".
+        assert_eq!(report.condition, Some(Condition::Rain));
+    }
+
+    #[test]
+    fn reduce_reports_spread() {
+        let provider1_reports = vec![forecast(11, 1.0), forecast(12, 2.0)];
+        let provider2_reports = vec![forecast(11, 3.0)];
+
+        let mut reducer = Reducer::new(date(11), 2, AggregationStrategy::Mean, 2);
+        reducer.push_provider_reports("provider1", 1.0, &provider1_reports);
+        reducer.push_provider_reports("provider2", 1.0, &provider2_reports);
+        let reports = reducer.reduce_reports();
+
+        let first_day = &reports[0].spread;
+        assert_eq!(first_day.providers, 2);
+        assert_eq!(first_day.min, 1.0);
+        assert_eq!(first_day.max, 3.0);
+        assert_eq!(first_day.stddev, 1.0);
+        assert!((first_day.confidence - 0.8).abs() < 0.001);
+
+        // A single provider agrees with itself but covers half of the providers.
+        let second_day = &reports[1].spread;
+        assert_eq!(second_day.providers, 1);
+        assert_eq!(second_day.stddev, 0.0);
+        assert_eq!(second_day.confidence, 0.5);
+    }
+
+    #[test]
+    fn reduce_reports_by_date() {
+        // The second provider skips the first day and returns the rest in reverse order.
+        let provider1_reports = vec![forecast(11, 1.0), forecast(12, 2.0)];
+        let provider2_reports = vec![forecast(13, 6.0), forecast(12, 4.0), forecast(10, 9.0)];
+
+        let mut reducer = Reducer::new(date(11), 2, AggregationStrategy::Mean, 2);
+        reducer.push_provider_reports("provider1", 1.0, &provider1_reports);
+        reducer.push_provider_reports("provider2", 1.0, &provider2_reports);
+        let reports = reducer.reduce_reports();
+
+        assert_eq!(
+            temperatures(&reports),
+            vec![(date(11), 1.0), (date(12), 3.0)]
+        );
+    }
+
+    #[test]
+    fn skip_days_without_reports() {
+        let mut reducer = Reducer::new(date(11), 3, AggregationStrategy::Mean, 1);
+        reducer.push_provider_reports("provider1", 1.0, &[forecast(12, 2.0)]);
         let reports = reducer.reduce_reports();
 
-        assert_eq!(reports, vec![Report::new(2.0), Report::new(3.0)]);
+        assert_eq!(temperatures(&reports), vec![(date(12), 2.0)]);
     }
 }
@@ -0,0 +1,135 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Fixed number of threads running provider calls. A job is only taken when there is
+/// a free worker for it, so jobs never wait in a queue.
+pub struct WorkerPool {
+    size: usize,
+    busy: Arc<AtomicUsize>,
+    sender: mpsc::Sender<Job>,
+}
+
+/// A worker reserved for a job. Freed when the job finishes or when dropped unused.
+pub struct Worker {
+    busy: Arc<AtomicUsize>,
+    sender: mpsc::Sender<Job>,
+}
+
+impl WorkerPool {
+    pub fn new(size: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        for _ in 0..size {
+            let receiver = Arc::clone(&receiver);
+
+            thread::spawn(move || loop {
+                let job = match receiver.lock().unwrap().recv() {
+                    Ok(job) => job,
+                    Err(_) => return,
+                };
+
+                // A panicking job shouldn't take the worker down.
+                panic::catch_unwind(AssertUnwindSafe(job)).ok();
+            });
+        }
+
+        Self {
+            size,
+            busy: Arc::new(AtomicUsize::new(0)),
+            sender,
+        }
+    }
+
+    /// Reserves a free worker. Returns `None` when all workers are busy.
+    pub fn try_reserve(&self) -> Option<Worker> {
+        self.busy
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |busy| {
+                if busy < self.size {
+                    Some(busy + 1)
+                } else {
+                    None
+                }
+            })
+            .ok()?;
+
+        Some(Worker {
+            busy: Arc::clone(&self.busy),
+            sender: self.sender.clone(),
+        })
+    }
+}
+
+impl Worker {
+    /// Runs the job on the reserved worker.
+    pub fn execute<F: FnOnce() + Send + 'static>(self, job: F) {
+        let sender = self.sender.clone();
+
+        sender
+            .send(Box::new(move || {
+                let _worker = self;
+                job();
+            }))
+            .ok();
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.busy.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn reserves_free_workers_only() {
+        let pool = WorkerPool::new(2);
+        let first = pool.try_reserve().unwrap();
+        let second = pool.try_reserve().unwrap();
+        assert!(pool.try_reserve().is_none());
+
+        // A reservation dropped unused frees the worker.
+        drop(second);
+        let second = pool.try_reserve().unwrap();
+
+        let (started, started_receiver) = mpsc::channel();
+        let (finish, finish_receiver) = mpsc::channel::<()>();
+        first.execute(move || {
+            started.send(()).unwrap();
+            finish_receiver.recv().ok();
+        });
+
+        started_receiver.recv().unwrap();
+        assert!(pool.try_reserve().is_none());
+        second.execute(|| panic!("Job failure"));
+        finish.send(()).unwrap();
+
+        // Both workers are freed, including the one whose job panicked.
+        let (done, done_receiver) = mpsc::channel();
+
+        for _ in 0..2 {
+            let worker = loop {
+                match pool.try_reserve() {
+                    Some(worker) => break worker,
+                    None => thread::sleep(Duration::from_millis(10)),
+                }
+            };
+
+            let done = done.clone();
+            worker.execute(move || done.send(()).unwrap());
+        }
+
+        for _ in 0..2 {
+            done_receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+    }
+}
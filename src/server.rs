@@ -1,6 +1,8 @@
 use std::error::Error;
+use std::time::Duration;
 
 use chrono::prelude::*;
+use iron::headers::{Authorization, Bearer};
 use iron::mime::Mime;
 use iron::prelude::*;
 use iron::{status, AfterMiddleware};
@@ -10,6 +12,8 @@ use serde_json::json;
 use simple_error::SimpleError;
 
 use super::config::CONFIG;
+use super::provider::PROVIDERS;
+use super::reporter::{self, AggregationStrategy, GeocodingError, QuorumError};
 
 // *********************
 // * Endpoint handlers *
@@ -18,57 +22,159 @@ use super::config::CONFIG;
 #[derive(Debug, Deserialize)]
 struct ForecastQueryParams {
     location: String,
+    /// Aggregation strategy, e.g. `median`. Defaults to the one from the config.
+    aggregate: Option<AggregationStrategy>,
+    /// Whether to include raw reports of each provider.
+    #[serde(default)]
+    breakdown: bool,
 }
 
+impl ForecastQueryParams {
+    fn report_options(&self, deadline: Option<Duration>) -> reporter::Options {
+        reporter::Options {
+            aggregation: self.aggregate,
+            breakdown: self.breakdown,
+            deadline,
+        }
+    }
+}
+
+// Header with the time in milliseconds the client is ready to wait for the forecast.
+const DEADLINE_HEADER: &str = "X-Request-Deadline";
+
 const FORECASTS_INDEX_DAYS: usize = 5;
 
 /// Returns an array of reports for the next 5 days.
 ///
 /// GET /forecasts
-/// Example response: `{"reports": [{"temperature": -12.3}, …]}`.
+/// Optional `aggregate` parameter sets the aggregation strategy: `mean`, `median`,
+/// `trimmed_mean`, `weighted_mean` or `envelope`.
+/// Optional `breakdown=true` adds raw reports of each provider to every day.
+/// Optional `X-Request-Deadline` header sets the time in ms to wait for providers.
+/// Example response:
+/// `{"reports": [{"date": "2019-03-11", "temperature": -12.3, …}, …], "sources": […]}`.
 fn forecasts_index_handler(request: &mut Request) -> IronResult<Response> {
     parse_query_params::<ForecastQueryParams>(request)
-        .and_then(|params| super::reporter::report(&params.location, FORECASTS_INDEX_DAYS))
-        .and_then(|reports| Ok(json_response(status::Ok, json!({ "reports": reports }))))
+        .and_then(|params| parse_deadline(request).map(|deadline| (params, deadline)))
+        .and_then(|(params, deadline)| {
+            let options = params.report_options(deadline);
+            reporter::report(&params.location, FORECASTS_INDEX_DAYS, &options)
+        })
+        .map(|forecast| json_response(status::Ok, json!(forecast)))
         .or_else(|error| Ok(error_response(error)))
 }
 
 /// Returns a report for the specified day.
 ///
 /// GET /forecasts/:date
-/// `date` has format "YYYY-MM-DD".
-/// Example response: `{"report": {"temperature": -12.3}}`.
+/// `date` has format "YYYY-MM-DD" and is a date in the location's time zone.
+/// Accepts the same `aggregate` and `breakdown` parameters and `X-Request-Deadline` header
+/// as the index.
+/// Example response: `{"report": {"date": "2019-03-11", "temperature": -12.3, …}, "sources": […]}`.
 fn forecasts_show_handler(request: &mut Request) -> IronResult<Response> {
     parse_query_params::<ForecastQueryParams>(request)
         .and_then(|params| parse_date(request).map(|date| (params, date)))
-        .and_then(|(params, date)| days_since_today(date).map(|days| (params, days)))
-        .and_then(|(params, days)| super::reporter::report(&params.location, days))
-        .and_then(|reports| {
-            let report = reports.last().unwrap();
-            Ok(json_response(status::Ok, json!({ "report": report })))
+        .and_then(|(params, date)| {
+            // The location's today is within a day of the UTC one. It's only known
+            // with the forecast so ask for enough days to cover the date anyway.
+            let utc_today = Utc::now().naive_utc().date();
+            let earliest_today = utc_today - chrono::Duration::days(1);
+            let latest_today = utc_today + chrono::Duration::days(1);
+            let days = days_since_today(date, earliest_today, latest_today)? + 1;
+
+            let options = params.report_options(parse_deadline(request)?);
+            let forecast = reporter::report(&params.location, days, &options)?;
+            days_since_today(date, forecast.today, forecast.today)?;
+            Ok(forecast.into_report(date)?)
+        })
+        .map(|(report, sources)| {
+            json_response(status::Ok, json!({ "report": report, "sources": sources }))
         })
         .or_else(|error| Ok(error_response(error)))
 }
 
+/// Returns the state of providers' circuit breakers and API keys.
+///
+/// GET /admin/providers
+/// Example response:
+/// `{"providers": [{"name": "Yandex", "circuit_breaker": {"state": "open", "retry_in_ms": 2500},
+/// "api_keys": [{"key": "****a1b2", "uses": 12, "quarantined_for_ms": 3540000}]}]}`.
+/// Other states are `{"state": "closed", "consecutive_failures": 1}` and `{"state": "half_open"}`.
+/// Requires `Authorization: Bearer <admin_token>` header. Responds with 401 Unauthorized
+/// to other requests and with 404 NotFound when no `admin_token` is configured.
+fn admin_providers_handler(
+    request: &mut Request,
+    admin_token: Option<&str>,
+) -> IronResult<Response> {
+    let admin_token = match admin_token {
+        Some(admin_token) => admin_token,
+        None => {
+            return Ok(json_response(
+                status::NotFound,
+                json!({"error": "Not found"}),
+            ))
+        }
+    };
+
+    let authorized = request
+        .headers
+        .get::<Authorization<Bearer>>()
+        .is_some_and(|authorization| constant_time_eq(&authorization.token, admin_token));
+
+    if !authorized {
+        return Ok(json_response(
+            status::Unauthorized,
+            json!({"error": "Unauthorized"}),
+        ));
+    }
+
+    let providers = PROVIDERS
+        .iter()
+        .map(|entry| {
+            json!({
+                "name": entry.provider.name(),
+                "circuit_breaker": entry.circuit_breaker.state(),
+                "api_keys": entry.keys.states(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    Ok(json_response(status::Ok, json!({ "providers": providers })))
+}
+
+/// Compares strings in time independent of their content so that response times
+/// don't give the admin token away.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |difference, (x, y)| difference | (x ^ y))
+            == 0
+}
+
 /// Parses `date` URL parameter to NaiveDate. Expected format is "YYYY-MM-DD".
 fn parse_date(request: &Request) -> Result<NaiveDate, Box<dyn Error>> {
     let router = request.extensions.get::<Router>().unwrap();
     let date_str = router.find("date").unwrap();
 
-    NaiveDate::parse_from_str(&date_str, "%Y-%m-%d")
+    NaiveDate::parse_from_str(date_str, "%Y-%m-%d")
         .map_err(|_| SimpleError::new("Invalid date format").into())
 }
 
-/// Counts the number of days between today and the given date.
-/// Returns `Ok(number_of_days)` when the value is valid and is between 0 and 5.
-/// Otherwise returns `Err(error)`.
-fn days_since_today(date: NaiveDate) -> Result<usize, Box<dyn Error>> {
-    let today = Local::now().naive_local().date();
-    let days_since_today = date.signed_duration_since(today).num_days();
+/// Counts the number of days between today and the given date when today is known to be
+/// between `earliest_today` and `latest_today`.
+/// Returns `Ok(number_of_days)` since `earliest_today` when the date may be between 0 and 5
+/// days since today. Otherwise returns `Err(error)`.
+fn days_since_today(
+    date: NaiveDate,
+    earliest_today: NaiveDate,
+    latest_today: NaiveDate,
+) -> Result<usize, Box<dyn Error>> {
+    let days_since_today = date.signed_duration_since(earliest_today).num_days();
 
     if days_since_today < 0 {
         Err(SimpleError::new("Date is in the past").into())
-    } else if days_since_today > 5 {
+    } else if date.signed_duration_since(latest_today).num_days() > 5 {
         Err(SimpleError::new("Date is more than 5 days in the future").into())
     } else {
         Ok(days_since_today as usize)
@@ -89,10 +195,54 @@ fn parse_query_params<'a, T: Deserialize<'a>>(request: &'a Request) -> Result<T,
         .and_then(|query_string| serde_qs::from_str::<T>(query_string).map_err(|e| e.into()))
 }
 
-/// Builds 422 UnprocessableEntity error with JSON body containing the error description.
+/// Parses the optional deadline header with the number of milliseconds.
+fn parse_deadline(request: &Request) -> Result<Option<Duration>, Box<dyn Error>> {
+    let values = match request.headers.get_raw(DEADLINE_HEADER) {
+        Some(values) => values,
+        None => return Ok(None),
+    };
+
+    values
+        .first()
+        .and_then(|value| String::from_utf8_lossy(value).trim().parse::<u64>().ok())
+        .map(|ms| Some(Duration::from_millis(ms)))
+        .ok_or_else(|| SimpleError::new("Invalid X-Request-Deadline header").into())
+}
+
+/// Builds an error response with JSON body containing the error description.
+/// Missing provider quorum for the forecast or the requested day responds with 502 BadGateway
+/// when no provider succeeded and 503 ServiceUnavailable otherwise. Geocoding past the deadline
+/// responds with 504 GatewayTimeout and without a free worker with 503 ServiceUnavailable.
+/// Other errors respond with 422 UnprocessableEntity.
 fn error_response(error: Box<dyn Error>) -> Response {
-    let body = json!({ "error": error.description() });
-    json_response(status::UnprocessableEntity, body)
+    let error = match error.downcast::<GeocodingError>() {
+        Ok(error) => {
+            let status = match *error {
+                GeocodingError::Timeout => status::GatewayTimeout,
+                GeocodingError::Busy => status::ServiceUnavailable,
+            };
+
+            return json_response(status, json!({ "error": error.to_string() }));
+        }
+        Err(error) => error,
+    };
+
+    match error.downcast::<QuorumError>() {
+        Ok(error) => {
+            let status = if error.succeeded == 0 {
+                status::BadGateway
+            } else {
+                status::ServiceUnavailable
+            };
+
+            let body = json!({ "error": error.to_string(), "sources": error.sources });
+            json_response(status, body)
+        }
+        Err(error) => {
+            let body = json!({ "error": error.to_string() });
+            json_response(status::UnprocessableEntity, body)
+        }
+    }
 }
 
 /// Builds HTTP response with JSON `body` and specified HTTP `status` code.
@@ -119,17 +269,25 @@ impl AfterMiddleware for Custom404 {
 
 /// Starts the server.
 pub fn start(config: Option<super::config::Server>) -> iron::error::HttpResult<iron::Listening> {
+    let server_config =
+        config.unwrap_or_else(|| CONFIG.with(|config| config.borrow().server.clone()));
+
+    // An empty token, e.g. from an unset environment variable, disables admin endpoints.
+    let admin_token = server_config
+        .admin_token
+        .filter(|admin_token| !admin_token.is_empty());
+    let admin_providers_handler =
+        move |request: &mut Request| admin_providers_handler(request, admin_token.as_deref());
+
     // Application routes.
     let router = router::router!(
         forecasts_index: get "/forecasts" => forecasts_index_handler,
         forecasts_show: get "/forecasts/:date" => forecasts_show_handler,
+        admin_providers: get "/admin/providers" => admin_providers_handler,
     );
 
     let mut chain = Chain::new(router);
     chain.link_after(Custom404);
 
-    let server_config =
-        config.unwrap_or_else(|| CONFIG.with(|config| config.borrow().server.clone()));
-
     Iron::new(chain).http(server_config.address)
 }
@@ -0,0 +1,80 @@
+use serde::Deserialize;
+
+use crate::support::mock_upstream::HEALTHY_LOCATION;
+use crate::support::shared_responses::ErrorResponse;
+use crate::support::test_server_client::{ADMIN_TOKEN, CLIENT};
+
+/// Gets the state of providers with the admin token.
+fn get_providers() -> ProvidersResponse {
+    let authorization = format!("Bearer {}", ADMIN_TOKEN);
+    let headers = [("Authorization", authorization.as_str())];
+    CLIENT.get_json_with_headers("/admin/providers", &headers, 200)
+}
+
+/// It should reject requests without the admin token.
+#[test]
+fn admin_token_required() {
+    let response = CLIENT.get_json::<ErrorResponse>("/admin/providers", 401);
+    assert_eq!(response.error, "Unauthorized");
+
+    let headers = [("Authorization", "Bearer admin-secre")];
+    CLIENT.get_json_with_headers::<ErrorResponse>("/admin/providers", &headers, 401);
+}
+
+/// It should return the state of providers' circuit breakers.
+#[test]
+fn providers_circuit_breakers() {
+    let response = get_providers();
+    let names = response
+        .providers
+        .iter()
+        .map(|p| p.name.as_str())
+        .collect::<Vec<_>>();
+
+    assert_eq!(names, vec!["Yandex", "OpenWeatherMap", "Nws", "MetNorway"]);
+
+    // Breakers of the testsuite are configured to stay closed.
+    for provider in &response.providers {
+        assert_eq!(provider.circuit_breaker.state, "closed");
+    }
+}
+
+/// It should quarantine the API key rejected by the upstream and keep using the other one.
+#[test]
+fn quarantined_api_keys() {
+    let url = format!("/forecasts?location={}", HEALTHY_LOCATION);
+    CLIENT.get_json::<serde_json::Value>(&url, 200);
+
+    let response = get_providers();
+    let keys = &response.providers[0].api_keys;
+
+    assert_eq!(keys.len(), 2);
+    assert_eq!(keys[0].key, "****oked");
+    assert!(keys[0].quarantined_for_ms.is_some());
+    assert!(keys[1].uses > 0);
+    assert_eq!(keys[1].quarantined_for_ms, None);
+}
+
+#[derive(Debug, Deserialize)]
+struct ProvidersResponse {
+    providers: Vec<Provider>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Provider {
+    name: String,
+    circuit_breaker: CircuitBreaker,
+    api_keys: Vec<ApiKey>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CircuitBreaker {
+    state: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiKey {
+    key: String,
+    uses: u64,
+    quarantined_for_ms: Option<u64>,
+}
@@ -1,8 +1,17 @@
-use chrono::{Duration, Local};
+use chrono::{Duration, Local, NaiveDate, Utc};
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
 use serde::Deserialize;
 
+use crate::support::mock_upstream::*;
 use crate::support::shared_responses::ErrorResponse;
-use crate::support::test_server_client::CLIENT;
+use crate::support::test_server_client::*;
+
+// Providers reporting all the locations. NWS covers `NWS_LOCATION` only, MET Norway reports
+// no days but for `MET_LOCATION` and `MET_EXPIRED_LOCATION`.
+const COVERING_PROVIDERS: [&str; 2] = ["Yandex", "OpenWeatherMap"];
 
 /// It should return a list of reports for the next 5 days.
 #[test]
@@ -10,6 +19,218 @@ fn week_forecast() {
     let url = "/forecasts?location=%D0%9C%D0%BE%D1%81%D0%BA%D0%B2%D0%B0";
     let response = CLIENT.get_json::<ForecastIndexResponse>(url, 200);
     assert_eq!(response.reports.len(), 5);
+
+    let today = Local::now().naive_local().date();
+
+    for (day, report) in response.reports.iter().enumerate() {
+        assert_eq!(report.date, today + Duration::days(day as i64));
+    }
+
+    // Tomorrow is a full day so both providers have daytime forecasts.
+    let expected = (yandex_temperature(1) + open_weather_map_temperature(1, 12)) / 2.0;
+    assert_eq!(response.reports[1].temperature, expected);
+}
+
+/// It should list each configured provider with the outcome of its call.
+#[test]
+fn week_forecast_sources() {
+    let url = format!("/forecasts?location={}", HEALTHY_LOCATION);
+    let response = CLIENT.get_json::<ForecastIndexResponse>(&url, 200);
+    assert_eq!(response.sources.len(), 4);
+
+    for provider in COVERING_PROVIDERS {
+        let source = response.source(provider);
+        assert_eq!(source.status, "ok");
+        assert_eq!(source.error, None);
+        assert!(source.latency_ms < 10_000);
+    }
+}
+
+/// It should call providers concurrently.
+#[test]
+fn concurrent_providers() {
+    let url = format!("/forecasts?location={}", SLOW_LOCATION);
+    let started_at = Instant::now();
+    let response = CLIENT.get_json::<ForecastIndexResponse>(&url, 200);
+    let elapsed_ms = started_at.elapsed().as_millis() as u64;
+
+    for provider in COVERING_PROVIDERS {
+        let source = response.source(provider);
+        assert_eq!(source.status, "ok");
+        assert!(source.latency_ms >= SLOW_RESPONSE_DELAY_MS);
+    }
+
+    // Sequential calls would take at least twice the delay.
+    assert!(elapsed_ms < SLOW_RESPONSE_DELAY_MS * 2);
+}
+
+/// It should return partial results when a provider misses the deadline.
+#[test]
+fn request_deadline() {
+    let url = format!("/forecasts?location={}", YANDEX_SLOW_LOCATION);
+    let started_at = Instant::now();
+    let headers = [("X-Request-Deadline", "200")];
+    let response = CLIENT.get_json_with_headers::<ForecastIndexResponse>(&url, &headers, 200);
+    let elapsed_ms = started_at.elapsed().as_millis() as u64;
+
+    assert!(elapsed_ms < SLOW_RESPONSE_DELAY_MS);
+    assert_eq!(response.source("Yandex").status, "timeout");
+    assert_eq!(response.source("OpenWeatherMap").status, "ok");
+    assert_eq!(
+        response.reports[1].temperature,
+        open_weather_map_temperature(1, 12)
+    );
+}
+
+/// It should fail once the deadline passes while geocoding.
+#[test]
+fn geocoder_deadline() {
+    let url = format!("/forecasts?location={}", GEOCODER_SLOW_LOCATION);
+    let started_at = Instant::now();
+    let headers = [("X-Request-Deadline", "200")];
+    let response = CLIENT.get_json_with_headers::<ErrorResponse>(&url, &headers, 504);
+    let elapsed_ms = started_at.elapsed().as_millis() as u64;
+
+    assert!(elapsed_ms < SLOW_RESPONSE_DELAY_MS);
+    assert_eq!(
+        response.error,
+        "Geocoding didn't finish before the deadline"
+    );
+}
+
+/// It should reject an invalid deadline.
+#[test]
+fn bad_request_deadline() {
+    let url = format!("/forecasts?location={}", HEALTHY_LOCATION);
+    let headers = [("X-Request-Deadline", "soon")];
+    let response = CLIENT.get_json_with_headers::<ErrorResponse>(&url, &headers, 422);
+    assert_eq!(response.error, "Invalid X-Request-Deadline header");
+}
+
+/// It should retry transient provider failures.
+#[test]
+fn provider_retries() {
+    let url = format!("/forecasts?location={}", FLAKY_LOCATION);
+    let response = CLIENT.get_json::<ForecastIndexResponse>(&url, 200);
+
+    for provider in COVERING_PROVIDERS {
+        let source = response.source(provider);
+        assert_eq!(source.status, "ok");
+        assert_eq!(source.retries, 1);
+    }
+}
+
+/// It should tell how much providers agree.
+#[test]
+fn week_forecast_spread() {
+    let url = format!("/forecasts?location={}", HEALTHY_LOCATION);
+    let response = CLIENT.get_json::<ForecastIndexResponse>(&url, 200);
+    let spread = &response.reports[1].spread;
+
+    let yandex = yandex_temperature(1);
+    let open_weather_map = open_weather_map_temperature(1, 12);
+    assert_eq!(spread.providers, 2);
+    assert_eq!(spread.min, yandex.min(open_weather_map));
+    assert_eq!(spread.max, yandex.max(open_weather_map));
+    assert_eq!(spread.stddev, (yandex - open_weather_map).abs() / 2.0);
+    assert!(spread.confidence >= 0.0 && spread.confidence < 1.0);
+}
+
+/// It should aggregate all weather values.
+#[test]
+fn week_forecast_weather_values() {
+    let url = format!("/forecasts?location={}", HEALTHY_LOCATION);
+    let response = CLIENT.get_json::<ForecastIndexResponse>(&url, 200);
+    let report = &response.reports[1];
+
+    let humidity = (YANDEX_HUMIDITY + OPEN_WEATHER_MAP_HUMIDITY) / 2.0;
+    assert_eq!(report.humidity, Some(humidity));
+
+    // OpenWeatherMap's precipitation is summed up over 8 slots of the day.
+    let precipitation = (YANDEX_PRECIPITATION + OPEN_WEATHER_MAP_SLOT_PRECIPITATION * 8.0) / 2.0;
+    assert_eq!(report.precipitation, Some(precipitation));
+
+    // Yandex has a bigger weight.
+    let condition = report.condition.as_ref().unwrap();
+    assert_eq!(condition.code, YANDEX_CONDITION);
+    assert_eq!(condition.label, "Cloudy");
+    assert_eq!(report.wind_speed, None);
+}
+
+/// It should return raw reports of each provider on request.
+#[test]
+fn week_forecast_with_breakdown() {
+    let url = format!("/forecasts?location={}&breakdown=true", HEALTHY_LOCATION);
+    let response = CLIENT.get_json::<ForecastIndexResponse>(&url, 200);
+    let breakdown = response.reports[1].breakdown.as_ref().unwrap();
+
+    assert_eq!(breakdown.len(), 2);
+    assert_eq!(breakdown["Yandex"].temperature, yandex_temperature(1));
+    assert_eq!(
+        breakdown["OpenWeatherMap"].temperature,
+        open_weather_map_temperature(1, 12)
+    );
+    assert_eq!(breakdown["Yandex"].humidity, Some(YANDEX_HUMIDITY));
+
+    // Breakdown is opt-in.
+    let url = format!("/forecasts?location={}", HEALTHY_LOCATION);
+    let response = CLIENT.get_json::<ForecastIndexResponse>(&url, 200);
+    assert!(response.reports[1].breakdown.is_none());
+}
+
+/// It should aggregate reports with the strategy from the request.
+#[test]
+fn week_forecast_with_aggregation_strategy() {
+    let url = format!(
+        "/forecasts?location={}&aggregate=envelope",
+        HEALTHY_LOCATION
+    );
+    let response = CLIENT.get_json::<ForecastIndexResponse>(&url, 200);
+    let report = &response.reports[1];
+
+    let yandex = yandex_temperature(1);
+    let open_weather_map_night = open_weather_map_temperature(1, 0);
+    let open_weather_map_day = open_weather_map_temperature(1, 12);
+
+    let midrange = (yandex + open_weather_map_day) / 2.0;
+    assert_eq!(report.temperature, midrange);
+    assert_eq!(
+        report.temperature_min,
+        Some(open_weather_map_night.min(yandex - 2.0))
+    );
+    assert_eq!(
+        report.temperature_max,
+        Some(open_weather_map_day.max(yandex + 2.0))
+    );
+}
+
+/// It should weigh providers' reports by their weights from the config.
+#[test]
+fn week_forecast_with_weights() {
+    let url = format!(
+        "/forecasts?location={}&aggregate=weighted_mean",
+        HEALTHY_LOCATION
+    );
+    let response = CLIENT.get_json::<ForecastIndexResponse>(&url, 200);
+    let report = &response.reports[1];
+
+    let total_weight = YANDEX_WEIGHT + OPEN_WEATHER_MAP_WEIGHT;
+    let yandex_share = YANDEX_WEIGHT / total_weight;
+    let open_weather_map_share = OPEN_WEATHER_MAP_WEIGHT / total_weight;
+
+    let expected = yandex_temperature(1) * yandex_share
+        + open_weather_map_temperature(1, 12) * open_weather_map_share;
+
+    assert_eq!(report.temperature, expected);
+    assert_eq!(report.weights["Yandex"], yandex_share);
+    assert_eq!(report.weights["OpenWeatherMap"], open_weather_map_share);
+}
+
+/// It should return an error on unknown aggregation strategy.
+#[test]
+fn bad_aggregation_strategy() {
+    let url = format!("/forecasts?location={}&aggregate=magic", HEALTHY_LOCATION);
+    CLIENT.get_json::<ErrorResponse>(&url, 422);
 }
 
 /// It should return a single averaged report for the given day.
@@ -19,10 +240,57 @@ fn date_forecast() {
 
     let url = format!(
         "/forecasts/{}?location=%D0%9C%D0%BE%D1%81%D0%BA%D0%B2%D0%B0",
-        date.format("%Y-%m-%d").to_string()
+        date.format("%Y-%m-%d")
     );
 
-    CLIENT.get_json::<ForecastShowResponse>(&url, 200);
+    let response = CLIENT.get_json::<ForecastShowResponse>(&url, 200);
+    assert_eq!(response.report.date, date.naive_local().date());
+    assert!(response.report.temperature.is_finite());
+    assert_eq!(response.sources.len(), 4);
+}
+
+/// It should return a report for today.
+#[test]
+fn today_forecast() {
+    let date = Local::now().naive_local().date();
+
+    let url = format!(
+        "/forecasts/{}?location=%D0%9C%D0%BE%D1%81%D0%BA%D0%B2%D0%B0",
+        date.format("%Y-%m-%d")
+    );
+
+    let response = CLIENT.get_json::<ForecastShowResponse>(&url, 200);
+    assert_eq!(response.report.date, date);
+}
+
+/// It should count days from today in the location's time zone rather than the server's one.
+#[test]
+fn far_time_zone_forecast() {
+    let url = format!("/forecasts?location={}", FAR_TIME_ZONE_LOCATION);
+    let response = CLIENT.get_json::<ForecastIndexResponse>(&url, 200);
+    assert_eq!(response.reports.len(), 5);
+
+    let today = Utc::now()
+        .with_timezone(&far_utc_offset())
+        .naive_local()
+        .date();
+
+    for (day, report) in response.reports.iter().enumerate() {
+        assert_eq!(report.date, today + Duration::days(day as i64));
+    }
+
+    let expected = (yandex_temperature(1) + open_weather_map_temperature(1, 12)) / 2.0;
+    assert_eq!(response.reports[1].temperature, expected);
+
+    // The last day is the 5th one since today at the location.
+    let date = today + Duration::days(5);
+    let url = format!(
+        "/forecasts/{}?location={}",
+        date.format("%Y-%m-%d"),
+        FAR_TIME_ZONE_LOCATION
+    );
+    let response = CLIENT.get_json::<ForecastShowResponse>(&url, 200);
+    assert_eq!(response.report.date, date);
 }
 
 /// It should return an error on invalid date format.
@@ -48,19 +316,238 @@ fn bad_location() {
     assert_eq!(response.error, "Failed to geocode location");
 }
 
+/// It should return reports from the remaining provider when the other one fails.
+#[test]
+fn provider_failure() {
+    let url = format!("/forecasts?location={}", YANDEX_DOWN_LOCATION);
+    let response = CLIENT.get_json::<ForecastIndexResponse>(&url, 200);
+    assert_eq!(response.reports.len(), 5);
+
+    // Only OpenWeatherMap contributes.
+    let expected = open_weather_map_temperature(1, 12);
+    assert_eq!(response.reports[1].temperature, expected);
+    assert_eq!(response.reports[1].weights.len(), 1);
+    assert_eq!(response.reports[1].spread.providers, 1);
+    assert_eq!(response.reports[1].spread.confidence, 1.0 / 3.0);
+
+    let yandex = response.source("Yandex");
+    assert_eq!(yandex.status, "http_error");
+    assert_eq!(yandex.retries, 2);
+    assert_eq!(
+        yandex.error,
+        Some(String::from("Unexpected HTTP status 500"))
+    );
+}
+
+/// It should report NWS as out of coverage outside the US without counting it
+/// against the quorum or the confidence.
+#[test]
+fn nws_out_of_coverage() {
+    let url = format!("/forecasts?location={}", YANDEX_DOWN_LOCATION);
+    let response = CLIENT.get_json::<ForecastIndexResponse>(&url, 200);
+
+    let nws = response.source("Nws");
+    assert_eq!(nws.status, "out_of_coverage");
+    assert_eq!(nws.error, Some(String::from("Location is out of coverage")));
+
+    // OpenWeatherMap alone meets the quorum and is one of three covering providers
+    // along with Yandex and MET Norway.
+    assert_eq!(response.reports[1].spread.providers, 1);
+    assert_eq!(response.reports[1].spread.confidence, 1.0 / 3.0);
+}
+
+/// It should look up the NWS gridpoint once and reuse it for the next forecasts.
+#[test]
+fn nws_gridpoint_cache() {
+    let url = format!("/forecasts?location={}", NWS_LOCATION);
+    let first = CLIENT.get_json::<ForecastIndexResponse>(&url, 200);
+    let point_requests = NWS_POINT_REQUESTS.load(Ordering::SeqCst);
+    let second = CLIENT.get_json::<ForecastIndexResponse>(&url, 200);
+
+    for response in [&first, &second] {
+        assert_eq!(response.source("Nws").status, "ok");
+        assert_eq!(response.reports[1].spread.providers, 3);
+        assert_eq!(response.reports[1].spread.max, nws_temperature(1));
+    }
+
+    assert_eq!(point_requests, 1);
+    assert_eq!(NWS_POINT_REQUESTS.load(Ordering::SeqCst), 1);
+}
+
+/// It should reuse the MET forecast until it expires.
+#[test]
+fn met_forecast_cache() {
+    let url = format!("/forecasts?location={}&breakdown=true", MET_LOCATION);
+
+    for _ in 0..2 {
+        let response = CLIENT.get_json::<ForecastIndexResponse>(&url, 200);
+        assert_eq!(response.source("MetNorway").status, "ok");
+        let breakdown = response.reports[1].breakdown.as_ref().unwrap();
+        assert_eq!(breakdown["MetNorway"].temperature, MET_TEMPERATURE);
+    }
+
+    assert_eq!(MET_REQUESTS.load(Ordering::SeqCst), 1);
+}
+
+/// It should revalidate the expired MET forecast and keep it when it isn't modified.
+#[test]
+fn met_forecast_revalidation() {
+    let url = format!(
+        "/forecasts?location={}&breakdown=true",
+        MET_EXPIRED_LOCATION
+    );
+
+    for _ in 0..2 {
+        let response = CLIENT.get_json::<ForecastIndexResponse>(&url, 200);
+        assert_eq!(response.source("MetNorway").status, "ok");
+        let breakdown = response.reports[1].breakdown.as_ref().unwrap();
+        assert_eq!(breakdown["MetNorway"].temperature, MET_TEMPERATURE);
+    }
+
+    assert_eq!(MET_EXPIRED_REQUESTS.load(Ordering::SeqCst), 2);
+    assert_eq!(MET_REVALIDATIONS.load(Ordering::SeqCst), 1);
+}
+
+/// It should fold OpenWeatherMap 3-hour slots into calendar days.
+#[test]
+fn open_weather_map_daily_aggregation() {
+    let url = format!("/forecasts?location={}", YANDEX_DOWN_LOCATION);
+    let response = CLIENT.get_json::<ForecastIndexResponse>(&url, 200);
+
+    // Today may be partial so check full days only.
+    for day in 1..4 {
+        let report = &response.reports[day];
+        let day = day as i64;
+        let night_temperature = open_weather_map_temperature(day, 0);
+        let daytime_temperature = open_weather_map_temperature(day, 12);
+        assert_eq!(report.temperature_min, Some(night_temperature));
+        assert_eq!(report.temperature_max, Some(daytime_temperature));
+    }
+}
+
+/// It should ignore a provider that responds with a malformed payload.
+#[test]
+fn malformed_provider_payload() {
+    let url = format!("/forecasts?location={}", YANDEX_GARBAGE_LOCATION);
+    let response = CLIENT.get_json::<ForecastIndexResponse>(&url, 200);
+    assert_eq!(response.reports.len(), 5);
+    assert_eq!(response.source("Yandex").status, "parse_error");
+    assert!(response.source("Yandex").error.is_some());
+    assert_eq!(response.source("OpenWeatherMap").status, "ok");
+}
+
+/// It should fail instead of serving fabricated data when all providers fail.
+#[test]
+fn all_providers_failure() {
+    let url = format!("/forecasts?location={}", PROVIDERS_DOWN_LOCATION);
+    let response = CLIENT.get_json::<QuorumErrorResponse>(&url, 502);
+    assert_eq!(response.error, "Only 0 of 1 required providers responded");
+    assert_eq!(response.sources.len(), 4);
+
+    for source in &response.sources[..2] {
+        assert_eq!(source.status, "http_error");
+    }
+}
+
+/// It should fail instead of serving the requested day when too few providers reported it.
+#[test]
+fn date_quorum_failure() {
+    let date = Local::now().naive_local().date() + Duration::days(2);
+
+    let url = format!(
+        "/forecasts/{}?location={}",
+        date.format("%Y-%m-%d"),
+        SHORT_FORECAST_LOCATION
+    );
+
+    let response = CLIENT.get_json::<QuorumErrorResponse>(&url, 502);
+    assert_eq!(
+        response.error,
+        format!("Only 0 of 1 required providers reported {}", date)
+    );
+    assert_eq!(response.sources.len(), 4);
+    assert_eq!(response.sources[0].status, "ok");
+    assert_eq!(response.sources[1].status, "http_error");
+}
+
+/// It should return an error when the geocoder fails.
+#[test]
+fn geocoder_failure() {
+    let url = format!("/forecasts?location={}", GEOCODER_DOWN_LOCATION);
+    let response = CLIENT.get_json::<ErrorResponse>(&url, 422);
+    assert_eq!(response.error, "Unexpected HTTP status 500");
+}
+
 /// Structs for parsing expected responses.
 
 #[derive(Debug, Deserialize)]
 struct ForecastIndexResponse {
     reports: Vec<Report>,
+    sources: Vec<Source>,
 }
 
 #[derive(Debug, Deserialize)]
 struct ForecastShowResponse {
     report: Report,
+    sources: Vec<Source>,
+}
+
+#[derive(Debug, Deserialize)]
+struct QuorumErrorResponse {
+    error: String,
+    sources: Vec<Source>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Source {
+    provider: String,
+    status: String,
+    latency_ms: u64,
+    retries: u32,
+    error: Option<String>,
+}
+
+impl ForecastIndexResponse {
+    fn source(&self, provider: &str) -> &Source {
+        self.sources
+            .iter()
+            .find(|s| s.provider == provider)
+            .unwrap()
+    }
 }
 
 #[derive(Debug, Deserialize)]
 struct Report {
+    date: NaiveDate,
     temperature: f32,
+    temperature_min: Option<f32>,
+    temperature_max: Option<f32>,
+    humidity: Option<f32>,
+    wind_speed: Option<f32>,
+    precipitation: Option<f32>,
+    condition: Option<Condition>,
+    weights: HashMap<String, f32>,
+    spread: Spread,
+    breakdown: Option<HashMap<String, ProviderReport>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProviderReport {
+    temperature: f32,
+    humidity: Option<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Spread {
+    providers: usize,
+    min: f32,
+    max: f32,
+    stddev: f32,
+    confidence: f32,
+}
+
+#[derive(Debug, Deserialize)]
+struct Condition {
+    code: String,
+    label: String,
 }
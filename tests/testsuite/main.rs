@@ -8,5 +8,6 @@ extern crate weather_report;
 mod support;
 
 // Integration test modules
+mod admin_test;
 mod generic_api_test;
 mod forecast_test;
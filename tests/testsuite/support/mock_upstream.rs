@@ -0,0 +1,462 @@
+//! Local mock of the upstream APIs (Yandex geocoder, Yandex Weather, OpenWeatherMap, NWS
+//! and MET Norway).
+//!
+//! The mock serves canned responses so that the testsuite doesn't depend on the network.
+//! Its behaviour is driven by the location: the geocoder resolves each known location
+//! to a latitude and the providers pick a scenario by that latitude.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time;
+
+use chrono::prelude::*;
+use chrono::Duration;
+use iron::mime::Mime;
+use iron::prelude::*;
+use iron::{status, Listening};
+use serde_json::json;
+
+/// Both providers respond with valid forecasts.
+pub const HEALTHY_LOCATION: &str = "Москва";
+/// Yandex responds with HTTP 500, OpenWeatherMap is fine.
+pub const YANDEX_DOWN_LOCATION: &str = "Yandexdown";
+/// Yandex responds with a malformed payload, OpenWeatherMap is fine.
+pub const YANDEX_GARBAGE_LOCATION: &str = "Yandexgarbage";
+/// Both providers respond with HTTP 500.
+pub const PROVIDERS_DOWN_LOCATION: &str = "Providersdown";
+/// Both providers respond with valid forecasts after `SLOW_RESPONSE_DELAY_MS`.
+pub const SLOW_LOCATION: &str = "Slowtown";
+/// Yandex responds with a valid forecast after `SLOW_RESPONSE_DELAY_MS`,
+/// OpenWeatherMap is fine.
+pub const YANDEX_SLOW_LOCATION: &str = "Yandexslow";
+/// Every other request fails: Yandex with HTTP 503 and OpenWeatherMap with HTTP 429.
+pub const FLAKY_LOCATION: &str = "Flakyville";
+/// The geocoder itself responds with HTTP 500.
+pub const GEOCODER_DOWN_LOCATION: &str = "Geocoderdown";
+/// The geocoder responds after `SLOW_RESPONSE_DELAY_MS`, providers are fine.
+pub const GEOCODER_SLOW_LOCATION: &str = "Geocoderslow";
+/// The only location covered by NWS, other providers are fine.
+pub const NWS_LOCATION: &str = "Nwsville";
+/// Both providers respond with valid forecasts in the time zone of `far_utc_offset`.
+pub const FAR_TIME_ZONE_LOCATION: &str = "Farzone";
+/// Yandex responds with a forecast for today only, OpenWeatherMap responds with HTTP 500.
+pub const SHORT_FORECAST_LOCATION: &str = "Shortforecast";
+/// MET Norway responds with a forecast expiring in an hour, other providers are fine.
+/// MET responds with an empty forecast for locations other than the MET ones.
+pub const MET_LOCATION: &str = "Metville";
+/// MET Norway responds with an expired forecast and with HTTP 304 to its revalidations.
+pub const MET_EXPIRED_LOCATION: &str = "Metexpired";
+
+// Known locations and their `(lat, lon)` coordinates.
+const LOCATIONS: &[(&str, f32, f32)] = &[
+    (HEALTHY_LOCATION, 55.75, 37.62),
+    (YANDEX_DOWN_LOCATION, 10.0, 10.0),
+    (YANDEX_GARBAGE_LOCATION, 20.0, 20.0),
+    (PROVIDERS_DOWN_LOCATION, 30.0, 30.0),
+    (SLOW_LOCATION, 40.0, 40.0),
+    (YANDEX_SLOW_LOCATION, 50.0, 50.0),
+    (FLAKY_LOCATION, 60.0, 60.0),
+    (FAR_TIME_ZONE_LOCATION, 70.0, 70.0),
+    (SHORT_FORECAST_LOCATION, 80.0, 80.0),
+    (GEOCODER_SLOW_LOCATION, 55.75, 37.62),
+    (NWS_LOCATION, 39.05, -95.68),
+    (MET_LOCATION, 59.91, 10.75),
+    (MET_EXPIRED_LOCATION, 58.97, 5.73),
+];
+
+// Counters of requests for the flaky location.
+static YANDEX_FLAKY_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+static OPEN_WEATHER_MAP_FLAKY_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of NWS point lookups of `NWS_LOCATION`.
+pub static NWS_POINT_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+// NWS point of `NWS_LOCATION` and its gridpoint.
+const NWS_POINT: &str = "39.05,-95.68";
+const NWS_GRIDPOINT: (&str, u32, u32) = ("TOP", 31, 80);
+
+/// Number of MET forecast requests for `MET_LOCATION`.
+pub static MET_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+/// Number of MET forecast requests for `MET_EXPIRED_LOCATION`.
+pub static MET_EXPIRED_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+/// Number of MET forecast revalidations for `MET_EXPIRED_LOCATION`.
+pub static MET_REVALIDATIONS: AtomicUsize = AtomicUsize::new(0);
+// `Last-Modified` of MET forecasts.
+const MET_LAST_MODIFIED: &str = "Mon, 11 Mar 2019 12:00:00 GMT";
+
+/// Yandex API key rejected with HTTP 403.
+pub const REVOKED_API_KEY: &str = "revoked";
+
+// Response delay of providers for the slow location.
+pub const SLOW_RESPONSE_DELAY_MS: u64 = 500;
+
+// Temperatures served for the first forecast day. Each next day is 1 degree warmer.
+pub const YANDEX_BASE_TEMPERATURE: f32 = -10.0;
+pub const OPEN_WEATHER_MAP_BASE_TEMPERATURE: f32 = -4.0;
+pub const NWS_BASE_TEMPERATURE: f32 = 5.0;
+// MET serves the same temperature for every hour.
+pub const MET_TEMPERATURE: f32 = 2.0;
+
+// Other weather values served by providers.
+pub const YANDEX_HUMIDITY: f32 = 80.0;
+pub const YANDEX_PRECIPITATION: f32 = 1.0;
+pub const YANDEX_CONDITION: &str = "cloudy";
+pub const OPEN_WEATHER_MAP_HUMIDITY: f32 = 60.0;
+pub const OPEN_WEATHER_MAP_SLOT_PRECIPITATION: f32 = 0.5;
+pub const OPEN_WEATHER_MAP_CONDITION: u32 = 804;
+
+// OpenWeatherMap serves forecasts by 3-hour slots for 5 days.
+const OPEN_WEATHER_MAP_MAX_SLOTS: usize = 40;
+
+pub struct MockUpstream {
+    listening: Listening,
+}
+
+impl MockUpstream {
+    pub fn start() -> Self {
+        let router = router::router!(
+            geocode: get "/1.x/" => geocode_handler,
+            yandex: get "/v1/forecast" => yandex_handler,
+            open_weather_map: get "/data/2.5/forecast" => open_weather_map_handler,
+            nws_points: get "/points/:point" => nws_points_handler,
+            nws_forecast: get "/gridpoints/:office/:grid/forecast" => nws_forecast_handler,
+            met_norway: get "/weatherapi/locationforecast/2.0/compact" => met_norway_handler,
+        );
+
+        let listening = Iron::new(router)
+            .http("127.0.0.1:0")
+            .expect("Failed to start mock upstream");
+
+        Self { listening }
+    }
+
+    /// Base URL of the mock to put into the app config.
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.listening.socket)
+    }
+}
+
+impl Drop for MockUpstream {
+    fn drop(&mut self) {
+        self.listening.close().expect("Error closing mock upstream");
+    }
+}
+
+/// GET /1.x/?geocode=…
+fn geocode_handler(request: &mut Request) -> IronResult<Response> {
+    let params = query_params(request);
+    let location = params.get("geocode").map(String::as_str).unwrap_or("");
+
+    if location == GEOCODER_DOWN_LOCATION {
+        return Ok(json_response(status::InternalServerError, "{}"));
+    }
+
+    if location == GEOCODER_SLOW_LOCATION {
+        thread::sleep(time::Duration::from_millis(SLOW_RESPONSE_DELAY_MS));
+    }
+
+    let feature_members = LOCATIONS
+        .iter()
+        .filter(|(name, _, _)| *name == location)
+        .map(|(_, lat, lon)| {
+            let pos = format!("{} {}", lon, lat);
+            json!({ "GeoObject": { "Point": { "pos": pos } } })
+        })
+        .collect::<Vec<_>>();
+
+    let body = json!({
+        "response": { "GeoObjectCollection": { "featureMember": feature_members } }
+    });
+
+    Ok(json_response(status::Ok, &body.to_string()))
+}
+
+/// GET /v1/forecast?lat=…&lon=…&limit=…
+fn yandex_handler(request: &mut Request) -> IronResult<Response> {
+    let api_key = request.headers.get_raw("X-Yandex-API-Key");
+    if api_key == Some(&[REVOKED_API_KEY.as_bytes().to_vec()][..]) {
+        return Ok(json_response(status::Forbidden, "{}"));
+    }
+
+    let params = query_params(request);
+    let mut limit = params
+        .get("limit")
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(7);
+
+    match scenario_latitude(&params) {
+        10 | 30 => return Ok(json_response(status::InternalServerError, "{}")),
+        20 => return Ok(json_response(status::Ok, "{\"forecasts\": [{\"parts\": ")),
+        40 | 50 => thread::sleep(time::Duration::from_millis(SLOW_RESPONSE_DELAY_MS)),
+        60 if is_flaky_failure(&YANDEX_FLAKY_REQUESTS) => {
+            return Ok(json_response(status::ServiceUnavailable, "{}"));
+        }
+        80 => limit = 1,
+        _ => (),
+    }
+
+    let utc_offset = scenario_utc_offset(&params);
+    let today = Utc::now().with_timezone(&utc_offset).naive_local().date();
+
+    let forecasts = (0..limit)
+        .map(|day: i64| {
+            let temp = yandex_temperature(day) as i64;
+
+            json!({
+                "date": (today + Duration::days(day)).format("%Y-%m-%d").to_string(),
+                "parts": {
+                    "day": {
+                        "temp_avg": temp,
+                        "temp_min": temp - 2,
+                        "temp_max": temp + 2,
+                        "humidity": YANDEX_HUMIDITY,
+                        "prec_mm": YANDEX_PRECIPITATION,
+                        "condition": YANDEX_CONDITION,
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let body = json!({
+        "info": { "tzinfo": { "offset": utc_offset.local_minus_utc() } },
+        "forecasts": forecasts,
+    });
+    Ok(json_response(status::Ok, &body.to_string()))
+}
+
+/// GET /data/2.5/forecast?lat=…&lon=…[&cnt=…]
+fn open_weather_map_handler(request: &mut Request) -> IronResult<Response> {
+    let params = query_params(request);
+
+    match scenario_latitude(&params) {
+        30 | 80 => return Ok(json_response(status::InternalServerError, "{}")),
+        40 => thread::sleep(time::Duration::from_millis(SLOW_RESPONSE_DELAY_MS)),
+        60 if is_flaky_failure(&OPEN_WEATHER_MAP_FLAKY_REQUESTS) => {
+            let mut response = json_response(status::TooManyRequests, "{}");
+            response.headers.set_raw("Retry-After", vec![b"0".to_vec()]);
+            return Ok(response);
+        }
+        _ => (),
+    }
+
+    let count = params
+        .get("cnt")
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(OPEN_WEATHER_MAP_MAX_SLOTS)
+        .min(OPEN_WEATHER_MAP_MAX_SLOTS);
+
+    // Slots start at the beginning of the current 3-hour interval.
+    let now = Utc::now();
+    let offset = now.hour() % 3 * 3600 + now.minute() * 60 + now.second();
+    let start = now.timestamp() - offset as i64;
+
+    // Serve the forecast in the same time zone as Yandex.
+    let local_offset = scenario_utc_offset(&params);
+    let today = Utc::now().with_timezone(&local_offset).naive_local().date();
+
+    let list = (0..count)
+        .map(|slot| {
+            let dt = start + slot as i64 * 3 * 3600;
+            let local_time = Utc.timestamp(dt, 0).with_timezone(&local_offset);
+            let day = (local_time.date().naive_local() - today).num_days();
+            let temp = open_weather_map_temperature(day, local_time.hour());
+            json!({
+                "dt": dt,
+                "main": { "temp": temp, "humidity": OPEN_WEATHER_MAP_HUMIDITY },
+                "weather": [{ "id": OPEN_WEATHER_MAP_CONDITION }],
+                "rain": { "3h": OPEN_WEATHER_MAP_SLOT_PRECIPITATION },
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let body = json!({
+        "cnt": count,
+        "list": list,
+        "city": { "timezone": local_offset.local_minus_utc() },
+    });
+
+    Ok(json_response(status::Ok, &body.to_string()))
+}
+
+/// GET /points/:lat,:lon
+/// Only `NWS_POINT` is covered, other points respond with HTTP 404.
+fn nws_points_handler(request: &mut Request) -> IronResult<Response> {
+    let point = request
+        .extensions
+        .get::<router::Router>()
+        .and_then(|params| params.find("point"))
+        .unwrap_or("");
+
+    if point != NWS_POINT {
+        return Ok(json_response(status::NotFound, "{}"));
+    }
+
+    NWS_POINT_REQUESTS.fetch_add(1, Ordering::SeqCst);
+
+    let (grid_id, grid_x, grid_y) = NWS_GRIDPOINT;
+    let body = json!({
+        "properties": { "gridId": grid_id, "gridX": grid_x, "gridY": grid_y },
+    });
+    Ok(json_response(status::Ok, &body.to_string()))
+}
+
+/// GET /gridpoints/:office/:x,:y/forecast?units=si
+/// Serves daytime periods in the local time zone of the testsuite like other providers.
+fn nws_forecast_handler(_request: &mut Request) -> IronResult<Response> {
+    let utc_offset = Local::now().offset().fix();
+    let today = Utc::now().with_timezone(&utc_offset).naive_local().date();
+
+    let periods = (0..7)
+        .map(|day: i64| {
+            let date = today + Duration::days(day);
+            let time = |hour| {
+                let time = date.and_hms(hour, 0, 0);
+                utc_offset.from_local_datetime(&time).unwrap().to_rfc3339()
+            };
+
+            json!({
+                "startTime": time(6),
+                "endTime": time(18),
+                "isDaytime": true,
+                "temperature": nws_temperature(day),
+                "shortForecast": "Sunny",
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let body = json!({ "properties": { "periods": periods } });
+    Ok(json_response(status::Ok, &body.to_string()))
+}
+
+/// GET /weatherapi/locationforecast/2.0/compact?lat=…&lon=…
+/// Serves hourly steps for 3 days from the current hour along with `Expires`
+/// and `Last-Modified`. Revalidations respond with HTTP 304. Other locations get
+/// an empty forecast expiring at once.
+fn met_norway_handler(request: &mut Request) -> IronResult<Response> {
+    let params = query_params(request);
+    let revalidation = request.headers.get_raw("If-Modified-Since")
+        == Some(&[MET_LAST_MODIFIED.as_bytes().to_vec()][..]);
+
+    let expires = match scenario_latitude(&params) {
+        30 => return Ok(json_response(status::InternalServerError, "{}")),
+        59 => {
+            MET_REQUESTS.fetch_add(1, Ordering::SeqCst);
+            Utc::now() + Duration::hours(1)
+        }
+        58 => {
+            MET_EXPIRED_REQUESTS.fetch_add(1, Ordering::SeqCst);
+
+            if revalidation {
+                MET_REVALIDATIONS.fetch_add(1, Ordering::SeqCst);
+            }
+            Utc::now() - Duration::hours(1)
+        }
+        _ => {
+            let body = json!({ "properties": { "timeseries": [] } });
+            return Ok(json_response(status::Ok, &body.to_string()));
+        }
+    };
+
+    let mut response = if revalidation {
+        Response::with(status::NotModified)
+    } else {
+        let now = Utc::now();
+        let start = now.timestamp() - i64::from(now.minute() * 60 + now.second());
+
+        let timeseries = (0..72)
+            .map(|hour| {
+                let time = Utc.timestamp(start + hour * 3600, 0);
+                json!({
+                    "time": time.to_rfc3339(),
+                    "data": {
+                        "instant": { "details": { "air_temperature": MET_TEMPERATURE } },
+                        "next_1_hours": { "summary": { "symbol_code": "cloudy" } },
+                    },
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let body = json!({ "properties": { "timeseries": timeseries } });
+        json_response(status::Ok, &body.to_string())
+    };
+
+    response
+        .headers
+        .set_raw("Expires", vec![expires.to_rfc2822().into_bytes()]);
+    response
+        .headers
+        .set_raw("Last-Modified", vec![MET_LAST_MODIFIED.as_bytes().to_vec()]);
+    Ok(response)
+}
+
+/// Yandex average daytime temperature for the `day` since today.
+/// Min and max temperatures are 2 degrees lower and higher.
+pub fn yandex_temperature(day: i64) -> f32 {
+    YANDEX_BASE_TEMPERATURE + day as f32
+}
+
+/// OpenWeatherMap slot temperature for the `day` since today at local `hour`.
+/// Daytime slots are 1 degree warmer than the base, other slots are 1 degree colder.
+pub fn open_weather_map_temperature(day: i64, hour: u32) -> f32 {
+    let base = OPEN_WEATHER_MAP_BASE_TEMPERATURE + day as f32;
+
+    if (12..18).contains(&hour) {
+        base + 1.0
+    } else {
+        base - 1.0
+    }
+}
+
+/// NWS daytime temperature for the `day` since today.
+pub fn nws_temperature(day: i64) -> f32 {
+    NWS_BASE_TEMPERATURE + day as f32
+}
+
+/// Parses query string of the request into a map.
+fn query_params(request: &Request) -> HashMap<String, String> {
+    request
+        .url
+        .query()
+        .and_then(|query| serde_qs::from_str(query).ok())
+        .unwrap_or_default()
+}
+
+/// Picks the scenario by the integer part of `lat` query parameter.
+fn scenario_latitude(params: &HashMap<String, String>) -> i64 {
+    params
+        .get("lat")
+        .and_then(|x| x.parse::<f32>().ok())
+        .map(|lat| lat as i64)
+        .unwrap_or(0)
+}
+
+/// UTC offset of the location picked by the scenario. Locations are in the local time zone
+/// of the testsuite except for `FAR_TIME_ZONE_LOCATION`.
+fn scenario_utc_offset(params: &HashMap<String, String>) -> FixedOffset {
+    match scenario_latitude(params) {
+        70 => far_utc_offset(),
+        _ => Local::now().offset().fix(),
+    }
+}
+
+/// UTC+14 or UTC-12, whichever is on another date than the local time zone of the testsuite.
+pub fn far_utc_offset() -> FixedOffset {
+    let today = Local::now().naive_local().date();
+
+    [14, -12]
+        .iter()
+        .map(|hours| FixedOffset::east(hours * 3600))
+        .find(|offset| Utc::now().with_timezone(offset).naive_local().date() != today)
+        .unwrap()
+}
+
+/// Counts the request and tells whether it should fail. Odd requests fail.
+fn is_flaky_failure(requests: &AtomicUsize) -> bool {
+    requests.fetch_add(1, Ordering::SeqCst).is_multiple_of(2)
+}
+
+fn json_response(status: status::Status, body: &str) -> Response {
+    let content_type = "application/json".parse::<Mime>().unwrap();
+    Response::with((content_type, status, body.to_string()))
+}
@@ -1,2 +1,3 @@
+pub mod mock_upstream;
 pub mod shared_responses;
 pub mod test_server_client;
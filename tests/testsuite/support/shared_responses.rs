@@ -1,4 +1,4 @@
-/// Shared structures for parsing API responses.
+//! Shared structures for parsing API responses.
 
 use serde::Deserialize;
 
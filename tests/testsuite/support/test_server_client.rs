@@ -1,29 +1,49 @@
-use std::sync::Mutex;
+use std::env;
+use std::fs;
+use std::process;
 
 use iron::Listening;
 use reqwest::Client;
 use serde::de::DeserializeOwned;
+use serde_json::json;
 
+use super::mock_upstream::{MockUpstream, REVOKED_API_KEY};
 use weather_report::config;
 
+// Provider weights for weighted aggregation.
+pub const YANDEX_WEIGHT: f32 = 3.0;
+pub const OPEN_WEATHER_MAP_WEIGHT: f32 = 1.0;
+
+/// Token required by admin endpoints.
+pub const ADMIN_TOKEN: &str = "admin-secret";
+
 lazy_static! {
-    static ref PORT_MUTEX: Mutex<u16> = Mutex::new(3000);
     pub static ref CLIENT: TestClient = TestClient::new(TestServer::new());
 }
 
 pub struct TestServer {
     listening: Listening,
+    _upstream: MockUpstream,
 }
 
 impl TestServer {
+    /// Starts the mock upstream and the app configured to use it.
     pub fn new() -> Self {
-        let mut port = PORT_MUTEX.lock().unwrap();
-        *port += 1; // Increment port number in mutex to avoid conflict in parallel tests.
+        let upstream = MockUpstream::start();
+        write_config(&upstream);
 
-        let address = format!("0.0.0.0:{}", port);
-        let server_config = Some(config::Server { address });
+        // Let the OS pick a free port to avoid conflicts.
+        let address = String::from("127.0.0.1:0");
+        let server_config = Some(config::Server {
+            address,
+            admin_token: Some(String::from(ADMIN_TOKEN)),
+        });
         let listening = weather_report::start_server(server_config);
-        Self { listening }
+
+        Self {
+            listening,
+            _upstream: upstream,
+        }
     }
 
     /// Base URL endpoint of the server.
@@ -42,6 +62,59 @@ impl Drop for TestServer {
     }
 }
 
+/// Writes the app config pointing all upstreams to the mock and makes the app use it.
+fn write_config(upstream: &MockUpstream) {
+    let base_url = upstream.base_url();
+    // Keep retries quick.
+    let http = json!({ "backoff_ms": 10, "max_backoff_ms": 50 });
+    // Tests share providers so failures of some tests mustn't skip providers for others.
+    let circuit_breaker = json!({ "failure_threshold": 1000 });
+
+    let config = json!({
+        "server": { "address": "127.0.0.1:0" },
+        "providers": [
+            {
+                "name": "Yandex",
+                // The revoked key is quarantined on the first call.
+                "api_keys": [REVOKED_API_KEY, "test"],
+                "base_url": base_url,
+                "weight": YANDEX_WEIGHT,
+                "http": http,
+                "circuit_breaker": circuit_breaker,
+            },
+            {
+                "name": "OpenWeatherMap",
+                "api_key": "test",
+                "base_url": base_url,
+                "weight": OPEN_WEATHER_MAP_WEIGHT,
+                "http": http,
+                "circuit_breaker": circuit_breaker,
+            },
+            {
+                // Covers `NWS_LOCATION` only.
+                "name": "Nws",
+                "user_agent": "weather-report-test",
+                "base_url": base_url,
+                "http": http,
+                "circuit_breaker": circuit_breaker,
+            },
+            {
+                // Reports days for `MET_LOCATION` and `MET_EXPIRED_LOCATION` only.
+                "name": "MetNorway",
+                "user_agent": "weather-report-test",
+                "base_url": base_url,
+                "http": http,
+                "circuit_breaker": circuit_breaker,
+            },
+        ],
+        "geocoder": { "api_key": "test", "base_url": base_url, "http": http },
+    });
+
+    let path = env::temp_dir().join(format!("weather_report_test_{}.json", process::id()));
+    fs::write(&path, config.to_string()).expect("Failed to write test config");
+    env::set_var("WEATHER_REPORT_CONFIG", path);
+}
+
 pub struct TestClient {
     server: TestServer,
     client: Client,
@@ -57,13 +130,26 @@ impl TestClient {
 
     /// Makes a GET request, asserts the status code and returns deserialized JSON body as `T`.
     pub fn get_json<T: DeserializeOwned>(&self, relative_url: &str, expected_status: u16) -> T {
+        self.get_json_with_headers(relative_url, &[], expected_status)
+    }
+
+    /// Same as `get_json` but sends additional `(name, value)` headers.
+    pub fn get_json_with_headers<T: DeserializeOwned>(
+        &self,
+        relative_url: &str,
+        headers: &[(&'static str, &str)],
+        expected_status: u16,
+    ) -> T {
         let url = format!("{}{}", self.server.base_url(), relative_url);
+        let mut request = self.client.get(&url);
 
-        let mut response = self
-            .client
-            .get(&url)
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+
+        let mut response = request
             .send()
-            .expect(&format!("Failed to make a GET request to {:?}", url));
+            .unwrap_or_else(|e| panic!("Failed to make a GET request to {:?}: {}", url, e));
 
         if response.status().as_u16() != expected_status {
             panic!(
@@ -74,9 +160,11 @@ impl TestClient {
             );
         }
 
-        response.json::<T>().expect(&format!(
-            "Failed to parse JSON response:\n{}\n",
-            response.text().unwrap()
-        ))
+        response.json::<T>().unwrap_or_else(|_| {
+            panic!(
+                "Failed to parse JSON response:\n{}\n",
+                response.text().unwrap()
+            )
+        })
     }
 }